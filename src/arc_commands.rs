@@ -1,151 +1,342 @@
 use serenity::utils::MessageBuilder;
+use std::fs;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use lazy_static::lazy_static;
 use fuzzy_matcher::FuzzyMatcher;
 use fuzzy_matcher::skim::SkimMatcherV2;
-use std::sync::RwLock;
-use lazy_static::lazy_static;
 use super::MessageHelper;
-use hash40::to_hash40;
+use super::converter::{self, human_size, UPLOAD_LIMIT};
+use super::labels::{self, hash40_value, LabelSource};
+use zip::{CompressionMethod, ZipWriter, write::FileOptions};
+use smash_arc::{ArcFile, ArcLookup, Hash40, Region};
 
-static ARC_HASH_STRINGS: &str = include_str!("hash40s.tsv");
-static PARAM_HASH_STRINGS: &str = include_str!("ParamLabels.csv");
+/// Every known label for a hash, along with which table it came from
+fn lookup_hash(hash: u64) -> Vec<(&'static str, String)> {
+    source_names(labels::lookup(hash))
+}
 
-lazy_static! {
-    static ref ARC_NAMES: HashMap<u64, &'static str> = {
-        ARC_HASH_STRINGS
-            .split('\n')
-            .filter_map(|line|{
-                let split: Vec<&'static str> = line.split('\t').collect();
-                if let &[hash, string] = &split[..] {
-                    Some((u64::from_str_radix(hash, 16).ok()?, string))
-                } else {
-                    None
-                }
-            })
-            .chain(
-                ARC_HASH_STRINGS
-                    .split('\n')
-                    .filter_map(|line|{
-                        let split: Vec<&'static str> = line.split('\t').collect();
-                        if let &[hash, string] = &split[..] {
-                            Some((u64::from_str_radix(hash, 16).ok()? & 0xFFFF_FFFF, string))
-                        } else {
-                            None
-                        }
-                    })
-            )
-            .collect()
-    };
+fn source_names(labels: Vec<(LabelSource, String)>) -> Vec<(&'static str, String)> {
+    labels
+        .into_iter()
+        .map(|(source, label)| (source.name(), label))
+        .collect()
+}
 
-    static ref PARAM_NAMES: HashMap<u64, &'static str> = {
-        PARAM_HASH_STRINGS
-            .split('\n')
-            .filter_map(|line|{
-                let split: Vec<&'static str> = line.split(',').collect();
-                if let &[hash, string] = &split[..] {
-                    let hash = hash.trim_start_matches("0x");
-                    Some((u64::from_str_radix(hash, 16).ok()?, string))
-                } else {
-                    None
+enum HashQuery {
+    Hash(u64),
+    Text(String),
+    Invalid(String),
+}
+
+/// Split on whitespace and commas, keeping "quoted strings" together
+fn parse_hash_queries(text: &str) -> Vec<HashQuery> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' if quoted => {
+                tokens.push(HashQuery::Text(std::mem::take(&mut current)));
+                quoted = false;
+            }
+            '"' if current.is_empty() => quoted = true,
+            c if !quoted && (c.is_whitespace() || c == ',') => {
+                if !current.is_empty() {
+                    tokens.push(parse_hash_query(&std::mem::take(&mut current)));
                 }
-            })
-            .chain(
-                PARAM_HASH_STRINGS
-                    .split('\n')
-                    .filter_map(|line|{
-                        let split: Vec<&'static str> = line.split(',').collect();
-                        if let &[hash, string] = &split[..] {
-                            let hash = hash.trim_start_matches("0x");
-                            Some((u64::from_str_radix(hash, 16).ok()? & 0xFFFF_FFFF, string))
-                        } else {
-                            None
-                        }
-                    })
-            )
-            .collect()
-    };
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(parse_hash_query(&current));
+    }
+
+    tokens
 }
 
+fn parse_hash_query(token: &str) -> HashQuery {
+    if token.starts_with("0x") || token.chars().all(|c| c.is_ascii_hexdigit()) {
+        match u64::from_str_radix(token.trim_start_matches("0x"), 16) {
+            Ok(hash) => HashQuery::Hash(hash),
+            Err(_) => HashQuery::Invalid(token.to_owned()),
+        }
+    } else {
+        HashQuery::Text(token.to_owned())
+    }
+}
+
+const UNKNOWN_HASH: &str = "(unknown)";
+
+fn hash_query_rows(query: &HashQuery) -> Vec<String> {
+    match query {
+        HashQuery::Hash(hash) => {
+            let labels = lookup_hash(*hash);
+            if labels.is_empty() {
+                vec![format!("0x{:010x}  {:<9} {}", hash, "", UNKNOWN_HASH)]
+            } else {
+                labels
+                    .into_iter()
+                    .map(|(source, label)| format!("0x{:010x}  {:<9} {}", hash, source, label))
+                    .collect()
+            }
+        }
+        HashQuery::Text(text) => vec![format!("0x{:010x}  {:<9} {}", hash40_value(text), "hashed", text)],
+        HashQuery::Invalid(token) => vec![format!("{:<12}  {:<9} (invalid hex literal)", token, "")],
+    }
+}
+
+/// Most rows to send inline before switching to a file upload
+const MAX_HASH_ROWS: usize = 20;
+
 pub fn hash(s: &str, message: &MessageHelper) {
-    let text = (&s[5..]).trim();
+    let text = s[4..].trim();
 
     if text == "check_param_hashes" {
-        message.say(format!("Loaded {} param hashes", PARAM_NAMES.len()));
+        message.say(
+            MessageBuilder::new()
+                .push_codeblock_safe(labels::stats(), None)
+                .build()
+        );
+        return;
     }
 
-    if text.starts_with("0x") || text.chars().all(|c| c.is_ascii_hexdigit()) {
-        let text = text.trim_start_matches("0x");
-        if let Ok(num) = u64::from_str_radix(text, 16) {
-            let arc_str = ARC_NAMES.get(&num);
-            let param_str = PARAM_NAMES.get(&num);
+    let mut queries = parse_hash_queries(text);
+    for attachment in &message.attachments {
+        match attachment.download() {
+            Ok(content) => queries.extend(parse_hash_queries(&String::from_utf8_lossy(&content))),
+            Err(e) => {
+                message.say_error(format!("Error downloading '{}': {:?}", attachment.filename, e));
+                return;
+            }
+        }
+    }
 
-            if arc_str.is_none() && param_str.is_none() {
-                message.say(format!("No matching string found for hash 0x{:X}", num));
-            } else {
-                if let Some(arc_str) = arc_str {
-                    message.say(
-                        MessageBuilder::new()
-                            .push("ARC match:")
-                            .push_codeblock_safe(arc_str, None)
-                            .build()
-                    );
-                }
-                if let Some(param_str) = param_str {
-                    message.say(
-                        MessageBuilder::new()
-                            .push("Param match:")
-                            .push_codeblock_safe(param_str, None)
-                            .build()
-                    );
-                }
+    if queries.is_empty() {
+        message.say_error("Use format 'hash [hash or string]...' or attach a text file with one per line");
+        return;
+    }
+
+    let mut rows: Vec<String> = Vec::new();
+    let mut unknown = 0;
+    for query in &queries {
+        let query_rows = hash_query_rows(query);
+        if let HashQuery::Hash(_) = query {
+            if query_rows.iter().any(|row| row.ends_with(UNKNOWN_HASH)) {
+                unknown += 1;
             }
-        } else {
-            message.say("Failed to parse. Invalid hex literal.");
         }
-    } else {
-        let text = text.trim_matches('"');
-        let hash = to_hash40(text);
-        let hash = ((hash.strlen() as u64) << 0x20) + hash.crc() as u64;
+        rows.extend(query_rows);
+    }
+    let summary = format!("{} queries, {} unknown hashes", queries.len(), unknown);
+    let table = rows.join("\n");
+
+    if rows.len() <= MAX_HASH_ROWS && table.len() < 1800 {
         message.say(
             MessageBuilder::new()
-                .push_codeblock_safe(format!("0x{:x}", hash), None)
+                .push(summary)
+                .push_codeblock_safe(table, None)
                 .build()
         );
+    } else {
+        let table = table + "\n";
+        let _ = message.send_file((table.as_bytes(), "hashes.txt"), summary)
+            .map_err(|e| {
+                message.say_error(format!("Error uploading results: {}", e));
+            });
     }
 }
 
-const SONG_NAME_CSV: &str = include_str!("song_name_to_file.tsv");
+const SCAN_HASHES_USAGE: &str = "Use format 'scan_hashes [align=4|8] [be] [known]' with files attached";
 
-lazy_static! {
-    static ref SONG_NAME_TO_FILE: RwLock<Option<HashMap<&'static str, Vec<String>>>>
-        = RwLock::new(None);
+struct HashCandidate {
+    offset: usize,
+    hash: u64,
+    labels: Vec<(&'static str, String)>,
 }
 
+/// Find every aligned value that could be a hash40: a string length in bits
+/// 32-39, a crc32 in bits 0-31, and nothing above that.
+fn scan_for_hashes(data: &[u8], align: usize, big_endian: bool) -> Vec<HashCandidate> {
+    (0..data.len().saturating_sub(7))
+        .step_by(align)
+        .filter_map(|offset| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[offset..offset + 8]);
+            let value = if big_endian {
+                u64::from_be_bytes(bytes)
+            } else {
+                u64::from_le_bytes(bytes)
+            };
+
+            let length = (value >> 32) & 0xFF;
+            let crc = value & 0xFFFF_FFFF;
+            if value >> 40 != 0 || length == 0 || crc == 0 {
+                return None;
+            }
+
+            Some(HashCandidate {
+                offset,
+                hash: value,
+                // most candidates are noise, keep them out of the lookup stats
+                labels: source_names(labels::lookup_uncounted(value)),
+            })
+        })
+        .collect()
+}
+
+pub fn scan_hashes(s: &str, message: &MessageHelper) {
+    let mut align = 4;
+    let mut big_endian = false;
+    let mut known_only = false;
+    for arg in s[11..].split_whitespace() {
+        match arg {
+            "be" => big_endian = true,
+            "le" => big_endian = false,
+            "known" => known_only = true,
+            "align=1" => align = 1,
+            "align=2" => align = 2,
+            "align=4" => align = 4,
+            "align=8" => align = 8,
+            _ => {
+                message.say_error(SCAN_HASHES_USAGE);
+                return;
+            }
+        }
+    }
+
+    if message.attachments.is_empty() {
+        message.say_error(SCAN_HASHES_USAGE);
+        return;
+    }
+
+    for attachment in &message.attachments {
+        let data = match attachment.download() {
+            Ok(data) => data,
+            Err(e) => {
+                message.say_error(format!("Error downloading '{}': {:?}", attachment.filename, e));
+                continue;
+            }
+        };
 
-pub fn setup_songs() {
-    let s: HashMap<_, _> =
-        SONG_NAME_CSV
-            .split('\n')
-            .filter_map(|a| {
-                let a: Vec<&'static str> = a.split('\t').collect();
-                if a.len() < 2 {
-                    None
+        let candidates = scan_for_hashes(&data, align, big_endian);
+        let known = candidates.iter().filter(|candidate| !candidate.labels.is_empty()).count();
+        let summary = format!(
+            "{}: {} candidate hashes, {} known, {} unknown",
+            attachment.filename,
+            candidates.len(),
+            known,
+            candidates.len() - known
+        );
+
+        let rows: Vec<String> = candidates
+            .iter()
+            .filter(|candidate| !known_only || !candidate.labels.is_empty())
+            .map(|candidate| {
+                let labels = if candidate.labels.is_empty() {
+                    String::from("(unknown)")
                 } else {
-                    let key = a[0];
-                    let values = a[1..].iter()
-                                        .map(|s| format!("bgm_{}.nus3audio", s))
-                                        .collect();
-                    Some((key, values))
-                }
+                    candidate.labels
+                        .iter()
+                        .map(|(source, label)| format!("{} [{}]", label, source))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                format!("0x{:08x}  0x{:010x}  {}", candidate.offset, candidate.hash, labels)
             })
             .collect();
 
-    *SONG_NAME_TO_FILE.write().unwrap() = Some(s);
+        if rows.is_empty() {
+            message.say(summary);
+            continue;
+        }
+
+        let table = format!("{:<10}  {:<12}  label\n{}", "offset", "hash", rows.join("\n"));
+        if rows.len() <= MAX_HASH_ROWS {
+            message.say(
+                MessageBuilder::new()
+                    .push(summary)
+                    .push_codeblock_safe(table, None)
+                    .build()
+            );
+        } else {
+            let table = table + "\n";
+            let file_name = format!("{}.hashes.txt", attachment.filename);
+            let _ = message.send_file((table.as_bytes(), &file_name[..]), summary)
+                .map_err(|e| {
+                    message.say_error(format!("Error uploading results: {}", e));
+                });
+        }
+    }
+}
+
+/// Root of the arc for the given version name, or the default `/arc` when no
+/// version is given. Other versions are mounted side by side in `/arcs/<version>`.
+fn arc_root(version: Option<&str>) -> Option<PathBuf> {
+    match version {
+        None => Some(PathBuf::from(ARC_ROOT)),
+        Some(version) => {
+            let valid = !version.is_empty()
+                && version.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+                && version.chars().any(|c| c != '.');
+            if valid {
+                Some(Path::new(ARC_VERSIONS_ROOT).join(version))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Split a leading `@version` argument off of a command's arguments
+fn take_version(args: &str) -> (Option<&str>, &str) {
+    let args = args.trim();
+    match args.strip_prefix('@') {
+        Some(versioned) => match versioned.find(char::is_whitespace) {
+            Some(i) => (Some(&versioned[..i]), versioned[i..].trim()),
+            None => (Some(versioned), ""),
+        },
+        None => (None, args),
+    }
+}
+
+/// The data.arc a mount was made from, kept next to it as `<mount>.data.arc`.
+/// Only needed for the compressed sizes %ls shows.
+pub fn data_arc_path(version: Option<&str>) -> Option<PathBuf> {
+    let root = arc_root(version)?;
+    Some(PathBuf::from(format!("{}.data.arc", root.display())))
 }
 
-fn to_arc_path(s: &str) -> Option<PathBuf> {
+lazy_static! {
+    /// data.arc file tables by path, which take a few seconds to read
+    static ref DATA_ARCS: Mutex<HashMap<PathBuf, Arc<ArcFile>>> = Mutex::new(HashMap::new());
+}
+
+fn open_data_arc(version: Option<&str>) -> Option<Arc<ArcFile>> {
+    let path = data_arc_path(version)?;
+    let mut data_arcs = DATA_ARCS.lock().unwrap();
+    if let Some(arc) = data_arcs.get(&path) {
+        return Some(arc.clone());
+    }
+
+    let arc = Arc::new(ArcFile::open(&path).ok()?);
+    data_arcs.insert(path, arc.clone());
+    Some(arc)
+}
+
+/// Size of a file as it's stored in data.arc, `None` for files the table
+/// doesn't have, like the stream: folder
+fn compressed_size(arc: &ArcFile, arc_path: &str) -> Option<u64> {
+    arc.get_file_data_from_hash(Hash40::from(arc_path), Region::UsEnglish)
+        .ok()
+        .map(|data| data.comp_size as u64)
+}
+
+fn to_arc_path(version: Option<&str>, s: &str) -> Option<PathBuf> {
+    let root = arc_root(version)?;
     let path = Path::new(s);
     if path.components().any(|c| c == std::path::Component::ParentDir) {
         return None;
@@ -155,221 +346,918 @@ fn to_arc_path(s: &str) -> Option<PathBuf> {
         .filter(|c| c != &std::path::Component::RootDir)
         .collect();
 
-    Some([Path::new("/arc"), &path].iter().collect())
+    Some([&root, &path].iter().collect())
+}
+
+const ARC_ROOT: &str = "/arc";
+const ARC_VERSIONS_ROOT: &str = "/arcs";
+const NUM_LINES: usize = 15;
+const LS_USAGE: &str = "Use format 'ls [@version] [path] [page] [--ext nutexb,numdlb] [--sort name|size] [--recursive]'";
+
+struct ArcEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    /// Compressed size in data.arc, only looked up for the entries on the page shown
+    stored_size: Option<u64>,
+}
+
+impl ArcEntry {
+    fn from_metadata(name: String, metadata: &fs::Metadata) -> Self {
+        ArcEntry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            stored_size: None,
+        }
+    }
+
+    fn extension(&self) -> String {
+        converter::extension(Path::new(&self.name)).to_lowercase()
+    }
+
+    fn to_line(&self) -> String {
+        if self.is_dir {
+            format!("[D] {:>10} {:>10}  {}/", "-", "-", self.name)
+        } else {
+            format!(
+                "[F] {:>10} {:>10}  {}",
+                human_size(self.size),
+                self.stored_size.map_or_else(|| String::from("-"), human_size),
+                self.name
+            )
+        }
+    }
+}
+
+fn list_dir(dir: &Path) -> std::io::Result<Vec<ArcEntry>> {
+    fs::read_dir(dir)?
+        .map(|entry| {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            Ok(ArcEntry::from_metadata(name, &entry.metadata()?))
+        })
+        .collect()
+}
+
+/// Most files a recursive walk collects before giving up, the whole arc has
+/// hundreds of thousands
+const MAX_WALK_FILES: usize = 5000;
+
+/// What a recursive walk may still collect before it gives up
+struct WalkBudget {
+    files: usize,
+    bytes: u64,
+}
+
+impl WalkBudget {
+    /// Listings only read metadata, so only the file count is bounded
+    fn listing() -> Self {
+        WalkBudget { files: MAX_WALK_FILES, bytes: u64::MAX }
+    }
+}
+
+fn walk_error(text: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, text)
+}
+
+/// Recursively collect every file under `dir`, named relative to `root`.
+/// Fails as soon as the files found go over the budget.
+fn walk_files(root: &Path, dir: &Path, files: &mut Vec<ArcEntry>, budget: &mut WalkBudget) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        if metadata.is_dir() {
+            walk_files(root, &path, files, budget)?;
+        } else {
+            if budget.files == 0 {
+                return Err(walk_error(format!("more than {} files, pick a smaller folder", files.len())));
+            }
+            budget.files -= 1;
+            budget.bytes = budget.bytes.checked_sub(metadata.len())
+                .ok_or_else(|| walk_error(String::from("too much data, pick a smaller folder")))?;
+
+            let name = path.strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .into_owned();
+            files.push(ArcEntry::from_metadata(name, &metadata));
+        }
+    }
+
+    Ok(())
+}
+
+/// Join the first `shown` items, noting how many more were left out so
+/// long lists still fit in a message
+fn summarize_list<S: AsRef<str>>(items: &[S], shown: usize, separator: &str) -> String {
+    let mut summary = items.iter()
+        .take(shown)
+        .map(|item| item.as_ref())
+        .collect::<Vec<_>>()
+        .join(separator);
+    if items.len() > shown {
+        summary.push_str(&format!("{}..., {} more", separator, items.len() - shown));
+    }
+
+    summary
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortBy {
+    Name,
+    Size,
 }
 
+fn sort_entries(entries: &mut [ArcEntry], sort: SortBy) {
+    match sort {
+        // folders first, then files, both alphabetical
+        SortBy::Name => entries.sort_by(|a, b| {
+            b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name))
+        }),
+        // folders first, then largest files first, ties broken by name so pages stay stable
+        SortBy::Size => entries.sort_by(|a, b| {
+            b.is_dir.cmp(&a.is_dir)
+                .then_with(|| b.size.cmp(&a.size))
+                .then_with(|| a.name.cmp(&b.name))
+        }),
+    }
+}
+
+pub fn page_count(len: usize) -> usize {
+    std::cmp::max(1, (len + (NUM_LINES - 1)) / NUM_LINES)
+}
 
 pub fn ls(s: &str, message: &MessageHelper) {
-    let args: Vec<_> = s[2..].trim().split(' ').collect();
-    let (path, page) = if let &[path] = args.as_slice() {
-        (path, 1usize)
-    } else if let &[path, page, ..] = args.as_slice() {
-        (
-            path,
-            match usize::from_str_radix(page, 10) {
-                Ok(page) => page,
-                Err(_) => {
-                    message.say(
-                        &MessageBuilder::new()
-                            .push("Error:")
-                            .push_codeblock_safe("Invalid page number. Use format 'ls [path] [page]'", None)
-                            .build()
-                    );
+    let (version, args) = take_version(&s[2..]);
+    let mut path = None;
+    let mut page = None;
+    let mut sort = SortBy::Name;
+    let mut exts: Vec<String> = Vec::new();
+    let mut recursive = false;
+
+    let mut args = args.split_whitespace();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--ext" | "-e" => match args.next() {
+                Some(ext) => exts.extend(
+                    ext.split(',')
+                        .filter(|ext| !ext.is_empty())
+                        .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                ),
+                None => {
+                    message.say_error(LS_USAGE);
                     return;
                 }
+            },
+            "--sort" | "-s" => match args.next() {
+                Some("name") => sort = SortBy::Name,
+                Some("size") => sort = SortBy::Size,
+                _ => {
+                    message.say_error(LS_USAGE);
+                    return;
+                }
+            },
+            "--recursive" | "-r" => recursive = true,
+            arg if path.is_none() => path = Some(arg),
+            arg if page.is_none() => match usize::from_str_radix(arg, 10) {
+                Ok(num) if num > 0 => page = Some(num),
+                _ => {
+                    message.say_error(format!("Invalid page number. {}", LS_USAGE));
+                    return;
+                }
+            },
+            _ => {
+                message.say_error(LS_USAGE);
+                return;
             }
-        )
-    } else {
-        message.say(
-            MessageBuilder::new()
-                .push("Error:")
-                .push_codeblock_safe("Invalid page number. Use format 'ls [path] [page]'", None)
-                .build()
-        );
+        }
+    }
+
+    // filtering by extension only makes sense over files, so it implies a recursive listing
+    let recursive = recursive || !exts.is_empty();
+    let is_root = path.map_or(true, |path| path.trim_matches('/').is_empty());
+    if recursive && is_root {
+        message.say_error("Recursive listings and --ext need a folder, the whole arc is too big to walk");
         return;
-    };
-    
-    let path = match to_arc_path(path.trim()) {
+    }
+
+    let page = page.unwrap_or(1);
+    let path = match to_arc_path(version, path.unwrap_or("")) {
         Some(path) => path,
         None => {
-            message.say(
-                MessageBuilder::new()
-                    .push("Error:")
-                    .push_codeblock_safe("Invalid path", None)
-                    .build()
-            );
+            message.say_error("Invalid path");
             return;
         }
     };
 
-    const NUM_LINES: usize = 15;
-
-    let (pages, result) =
-        Command::new("ls").arg("-lh").arg(&path).output()
-            .map(|out|{
-                let output = String::new() +
-                    std::str::from_utf8(&out.stdout).unwrap() +
-                    std::str::from_utf8(&out.stderr).unwrap();
-                let output = output.split('\n').collect::<Vec<_>>();
-                let line_count = output.len();
-                let page_count = (line_count + (NUM_LINES - 1)) / NUM_LINES;
-                (
-                    page_count,
-                    output
-                        .into_iter()
-                        .skip((page - 1) * NUM_LINES)
-                        .take(NUM_LINES)
-                        .collect::<Vec<_>>()
-                        .join("\n")
-                )
-            })
-            .unwrap_or_else(|e| (0, e.to_string()));
+    let listing = if recursive {
+        let mut files = Vec::new();
+        walk_files(&path, &path, &mut files, &mut WalkBudget::listing()).map(|_| files)
+    } else {
+        list_dir(&path)
+    };
+
+    let mut entries = match listing {
+        Ok(entries) => entries,
+        Err(e) => {
+            message.say_error(format!("Could not list '{}': {}", path.display(), e));
+            return;
+        }
+    };
+
+    if !exts.is_empty() {
+        entries.retain(|entry| exts.contains(&entry.extension()));
+    }
+    sort_entries(&mut entries, sort);
+
+    let pages = page_count(entries.len());
+    if page > pages {
+        message.say_error(format!("Page {} is out of range, there are {} pages", page, pages));
+        return;
+    }
+
+    let file_count = entries.iter().filter(|entry| !entry.is_dir).count();
+    let total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+
+    let data_arc = open_data_arc(version);
+    if let Some(arc) = &data_arc {
+        // entry names are relative to the listed folder, data.arc hashes the full path
+        let folder = arc_root(version)
+            .and_then(|root| path.strip_prefix(root).ok().map(|folder| folder.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        for entry in entries.iter_mut().skip((page - 1) * NUM_LINES).take(NUM_LINES).filter(|entry| !entry.is_dir) {
+            let arc_path = if folder.is_empty() { entry.name.clone() } else { format!("{}/{}", folder, entry.name) };
+            entry.stored_size = compressed_size(arc, &arc_path);
+        }
+    }
+
+    let result = if entries.is_empty() {
+        String::from("(no matching entries)")
+    } else {
+        entries
+            .iter()
+            .skip((page - 1) * NUM_LINES)
+            .take(NUM_LINES)
+            .map(ArcEntry::to_line)
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
 
     message.say(
         MessageBuilder::new()
             .push(path.to_str().unwrap())
-            .push(format!(" Page {}/{}", page, pages))
-            .push_codeblock_safe(result, None)
+            .push(format!(
+                " Page {}/{} ({} folders, {} files, {} total){}",
+                page,
+                pages,
+                entries.len() - file_count,
+                file_count,
+                human_size(total_size),
+                if data_arc.is_some() { "" } else { ", compressed sizes need the data.arc next to the mount" }
+            ))
+            .push_codeblock_safe(
+                format!("    {:>10} {:>10}  name\n{}", "size", "compressed", result),
+                None
+            )
             .build()
     );
 }
 
-pub fn get(s: &str, message: &MessageHelper) {
-    let path = match to_arc_path(s[3..].trim()) {
-        Some(path) => path,
-        None => {
-            message.say(
-                MessageBuilder::new()
-                    .push("Error:")
-                    .push_codeblock_safe("Invalid path", None)
-                    .build()
-            );
-            return;
+lazy_static! {
+    /// Every distinct file path known to the arc hash table, sorted
+    static ref ARC_PATHS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+}
+
+/// Rebuild the path list %find_file searches, after the labels are reloaded
+pub fn reload_arc_paths() {
+    let mut paths = Vec::new();
+    labels::for_each_arc_path(|path| {
+        let is_file = path.rsplit('/')
+            .next()
+            .map(|file_name| file_name.contains('.'))
+            .unwrap_or(false);
+        if is_file {
+            paths.push(path.to_owned());
         }
+    });
+    paths.sort_unstable();
+    paths.dedup();
+    *ARC_PATHS.write().unwrap() = paths;
+}
+
+const FIND_FILE_USAGE: &str = "Use format 'find_file [@version] [--glob|--regex|--fuzzy] [pattern] [page]'";
+
+enum FileSearch {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+    Fuzzy(String),
+}
+
+impl FileSearch {
+    /// Search using the given mode, or guess it from the pattern when none is given
+    fn new(mode: Option<&str>, pattern: &str) -> Result<Self, String> {
+        let is_glob = pattern.contains(&['*', '?', '['][..]);
+        match mode {
+            Some("--regex") => regex::Regex::new(pattern)
+                .map(FileSearch::Regex)
+                .map_err(|e| e.to_string()),
+            Some("--glob") => glob::Pattern::new(pattern)
+                .map(FileSearch::Glob)
+                .map_err(|e| e.to_string()),
+            Some("--fuzzy") => Ok(FileSearch::Fuzzy(pattern.to_owned())),
+            None if is_glob => FileSearch::new(Some("--glob"), pattern),
+            None => FileSearch::new(Some("--fuzzy"), pattern),
+            Some(_) => Err(FIND_FILE_USAGE.to_owned()),
+        }
+    }
+
+    /// Matching paths, best match first
+    fn search(&self) -> Vec<String> {
+        let paths = ARC_PATHS.read().unwrap();
+        match self {
+            FileSearch::Glob(pattern) => paths
+                .iter()
+                .filter(|path| pattern.matches(path))
+                .cloned()
+                .collect(),
+            FileSearch::Regex(regex) => paths
+                .iter()
+                .filter(|path| regex.is_match(path))
+                .cloned()
+                .collect(),
+            FileSearch::Fuzzy(pattern) => {
+                let matcher = SkimMatcherV2::default();
+                let mut paths: Vec<(i64, &String)> = paths
+                    .iter()
+                    .filter_map(|path| {
+                        matcher.fuzzy_match(path, pattern)
+                            .map(|score| (score, path))
+                    })
+                    .collect();
+                paths.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+                paths.into_iter().map(|(_, path)| path.clone()).collect()
+            }
+        }
+    }
+}
+
+pub fn find_file(s: &str, message: &MessageHelper) {
+    let (version, args) = take_version(&s[9..]);
+    let mut args: Vec<&str> = args.split_whitespace().collect();
+
+    let mode = match args.first() {
+        Some(arg) if arg.starts_with("--") => Some(args.remove(0)),
+        _ => None,
     };
 
-    message.send_file(path.to_str().unwrap(), path.to_str().unwrap())
-        .map_err(|e| {
-            message.say(
-                MessageBuilder::new()
-                    .push(format!("Error getting '{}':", path.to_str().unwrap()))
-                    .push_codeblock_safe(e.to_string(), None)
-                    .build()
-            );
-        })
-        .unwrap();
-}
-
-pub fn find_song(s: &str, message: &MessageHelper) {
-    let name = s[9..].trim().trim_matches('"');
-    let matcher = SkimMatcherV2::default();
-    let song_name_to_file = 
-        SONG_NAME_TO_FILE
-            .read()
-            .unwrap();
-    let song_name_to_file = 
-        song_name_to_file
-            .as_ref()
-            .unwrap();
-    let mut songs: Vec<(i64, &str)> =
-        song_name_to_file
-            .keys()
-            .filter_map(|song_name|{
-                matcher.fuzzy_match(song_name, name)
-                    .map(|score| (score, *song_name))
-            })
-            .collect();
+    let page = match args.as_slice() {
+        [_, .., page] => match usize::from_str_radix(page, 10) {
+            Ok(num) if num > 0 => {
+                args.pop();
+                num
+            }
+            _ => 1,
+        },
+        _ => 1,
+    };
+
+    let pattern = match args.as_slice() {
+        [pattern] => pattern.trim_matches('"'),
+        _ => {
+            message.say_error(FIND_FILE_USAGE);
+            return;
+        }
+    };
 
-    songs.sort_by_cached_key(|a| -a.0);
+    let search = match FileSearch::new(mode, pattern) {
+        Ok(search) => search,
+        Err(e) => {
+            message.say_error(e);
+            return;
+        }
+    };
 
-    const LINES: usize = 15;
+    let paths = search.search();
+    if paths.is_empty() {
+        message.say(format!("No files found for '{}'", pattern));
+        return;
+    }
 
-    if songs.is_empty() {
-        message.say(format!("Song '{}' not found", name));
+    let pages = page_count(paths.len());
+    if page > pages {
+        message.say_error(format!("Page {} is out of range, there are {} pages", page, pages));
         return;
     }
 
-    let songs = songs
-        .into_iter()
-        .take(LINES)
-        .map(|a|{
-            format!(
-                "{} - {}",
-                a.1,
-                song_name_to_file
-                    .get(a.1)
-                    .unwrap()
-                    .join(", ")
-            )
+    // only stat the files actually shown, the mount is slow to query
+    let result = paths
+        .iter()
+        .skip((page - 1) * NUM_LINES)
+        .take(NUM_LINES)
+        .map(|path| {
+            let size = to_arc_path(version, path)
+                .and_then(|arc_path| fs::metadata(arc_path).ok())
+                .map(|metadata| human_size(metadata.len()))
+                .unwrap_or_else(|| String::from("missing"));
+            format!("{:>10}  {}", size, path)
         })
         .collect::<Vec<_>>()
         .join("\n");
 
     message.say(
         MessageBuilder::new()
-            .push(format!("Found for '{}':", name))
-            .push_codeblock_safe(songs, None)
+            .push(format!(
+                "Found {} files for '{}' Page {}/{}",
+                paths.len(),
+                pattern,
+                page,
+                pages
+            ))
+            .push_codeblock_safe(result, None)
             .build()
     );
 }
 
-pub fn get_song(s: &str, message: &MessageHelper) {
-    let name = s[8..].trim().trim_matches('"');
-    let matcher = SkimMatcherV2::default();
-    let song_name_to_file = 
-        SONG_NAME_TO_FILE
-            .read()
-            .unwrap();
-    let song_name_to_file = 
-        song_name_to_file
-            .as_ref()
-            .unwrap();
-    let mut songs: Vec<(i64, &str)> =
-        song_name_to_file
-            .keys()
-            .filter_map(|song_name|{
-                matcher.fuzzy_match(song_name, name)
-                    .map(|score| (score, *song_name))
-            })
-            .chain(song_name_to_file.keys().filter(
-                |s| s.trim() == name.trim()).map(|a| (std::i64::MAX - 1, *a))
-            )
-            .collect();
-    
-    if songs.is_empty() {
-        message.say(format!("Song '{}' not found", name));
-        return;
+/// Copy a file out of the arc into the converter's scratch folder, since
+/// converting consumes its input file
+pub fn copy_for_conversion(path: &Path) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(super::TEMP_DIR)?;
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    let temp_path = Path::new(super::TEMP_DIR).join(file_name);
+    fs::copy(path, &temp_path)?;
+    Ok(temp_path)
+}
+
+pub fn convert_from_arc(path: &Path, convert_args: &str, message: &MessageHelper) {
+    match copy_for_conversion(path) {
+        Ok(temp_path) => {
+            message.broadcast_typing();
+            super::send_converted(temp_path, convert_args, message);
+        }
+        Err(e) => message.say_error(format!("Could not read '{}': {}", path.display(), e)),
     }
+}
 
-    songs.sort_by_cached_key(|a| -a.0);
+/// Most zip parts a single folder request will be split into
+pub const MAX_ZIP_PARTS: usize = 5;
+/// Most files a single folder request will send
+const MAX_FOLDER_FILES: usize = 1000;
+/// Most file names listed in an error message
+const LISTED_FILES: usize = 20;
+/// Most conversion failures listed after sending a folder, each with its error
+const LISTED_FAILURES: usize = 10;
 
-    let file_names = song_name_to_file.get(songs[0].1).unwrap();
+pub enum ZipSource {
+    Arc(PathBuf),
+    Converted(Vec<u8>),
+}
 
-    let file_names: Vec<_> = 
-        file_names
-            .into_iter()
-            .map(|file_name| format!("/arc/stream:/sound/bgm/{}", file_name))
-            .collect();
-    let file_names: Vec<_> = file_names.iter().map(|a| &**a).collect();
-    dbg!(&file_names);
+pub struct ZipEntry {
+    pub name: String,
+    pub size: u64,
+    pub source: ZipSource,
+}
 
-    for (i, file_name) in file_names.into_iter().enumerate() {
-        let content = if i == 0 {
-            format!("Song '{}':", name)
+fn write_zip_part(path: &Path, entries: &[ZipEntry]) -> zip::result::ZipResult<u64> {
+    let mut zip = ZipWriter::new(fs::File::create(path)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for entry in entries {
+        zip.start_file(entry.name.as_str(), options)?;
+        match &entry.source {
+            ZipSource::Arc(path) => {
+                std::io::copy(&mut fs::File::open(path)?, &mut zip)?;
+            }
+            ZipSource::Converted(data) => zip.write_all(data)?,
+        }
+    }
+    zip.finish()?;
+
+    Ok(fs::metadata(path)?.len())
+}
+
+/// Greedily group entries into parts that each fit under the upload limit
+/// before compression. A file larger than the limit gets a part to itself.
+fn split_into_parts(entries: Vec<ZipEntry>) -> Vec<Vec<ZipEntry>> {
+    let mut parts: Vec<Vec<ZipEntry>> = Vec::new();
+    let mut part_size = 0;
+    for entry in entries {
+        match parts.last_mut() {
+            Some(part) if part_size + entry.size <= UPLOAD_LIMIT => {
+                part_size += entry.size;
+                part.push(entry);
+            }
+            _ => {
+                part_size = entry.size;
+                parts.push(vec![entry]);
+            }
+        }
+    }
+
+    parts
+}
+
+/// Zip up entries and upload them, split into as many parts as needed to
+/// stay under the upload limit. `description` names what is being sent in messages.
+pub fn send_zip(entries: Vec<ZipEntry>, zip_name: &str, description: &str, message: &MessageHelper) {
+    let parts = split_into_parts(entries);
+    if parts.len() > MAX_ZIP_PARTS {
+        message.say_error(format!(
+            "{} would need {} zip parts (max {})",
+            description,
+            parts.len(),
+            MAX_ZIP_PARTS
+        ));
+        return;
+    }
+
+    let part_count = parts.len();
+
+    if let Err(e) = fs::create_dir_all(super::TEMP_DIR) {
+        message.say_error(format!("Error creating dir: {}", e));
+        return;
+    }
+
+    for (i, part) in parts.iter().enumerate() {
+        let part_name = if part_count == 1 {
+            format!("{}.zip", zip_name)
         } else {
-            String::new()
+            format!("{}.part{}of{}.zip", zip_name, i + 1, part_count)
+        };
+        let zip_path = Path::new(super::TEMP_DIR).join(&part_name);
+
+        match write_zip_part(&zip_path, part) {
+            Ok(size) if size > UPLOAD_LIMIT => {
+                message.say_error(format!(
+                    "{} is {} after compression, over the {} upload limit. Files: {}",
+                    part_name,
+                    human_size(size),
+                    human_size(UPLOAD_LIMIT),
+                    summarize_list(
+                        &part.iter().map(|entry| &entry.name[..]).collect::<Vec<_>>(),
+                        LISTED_FILES,
+                        ", "
+                    )
+                ));
+            }
+            Ok(_) => {
+                let content = format!("{} ({} files) part {}/{}", description, part.len(), i + 1, part_count);
+                let _ = message.send_file(zip_path.to_str().unwrap(), content)
+                    .map_err(|e| {
+                        message.say(
+                            MessageBuilder::new()
+                                .push(format!("Error uploading '{}':", part_name))
+                                .push_codeblock_safe(e.to_string(), None)
+                                .build()
+                        );
+                    });
+            }
+            Err(e) => message.say_error(format!("Error writing {}: {:?}", part_name, e)),
+        }
+
+        let _ = fs::remove_file(&zip_path);
+    }
+}
+
+fn convert_for_zip(path: &Path, name: &str, convert_args: &str) -> Result<ZipEntry, String> {
+    let temp_path = copy_for_conversion(path).map_err(|e| e.to_string())?;
+    let (converted, _) = converter::convert(&temp_path, convert_args)
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            e.message
+        })?;
+    let data = fs::read(&converted).map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(&converted);
+
+    let name = Path::new(name)
+        .with_file_name(converted.file_name().unwrap_or_default())
+        .to_string_lossy()
+        .into_owned();
+
+    Ok(ZipEntry {
+        name,
+        size: data.len() as u64,
+        source: ZipSource::Converted(data),
+    })
+}
+
+/// File count and total size of a folder for error messages
+fn folder_summary(path: &Path) -> String {
+    let mut files = Vec::new();
+    match walk_files(path, path, &mut files, &mut WalkBudget::listing()) {
+        Ok(()) => format!(
+            "{} files, {}",
+            files.len(),
+            human_size(files.iter().map(|file| file.size).sum())
+        ),
+        Err(_) => format!("over {} files", MAX_WALK_FILES),
+    }
+}
+
+fn get_folder(path: &Path, convert_args: Option<&str>, message: &MessageHelper) {
+    // stop walking as soon as the folder can't fit in the zip parts we're willing to send
+    let mut budget = WalkBudget {
+        files: MAX_FOLDER_FILES,
+        bytes: MAX_ZIP_PARTS as u64 * UPLOAD_LIMIT,
+    };
+    let mut files = Vec::new();
+    if let Err(e) = walk_files(path, path, &mut files, &mut budget) {
+        message.say_error(format!(
+            "'{}' is too large to send ({}; max {} files, {} zip parts of {} each): {}",
+            path.display(),
+            folder_summary(path),
+            MAX_FOLDER_FILES,
+            MAX_ZIP_PARTS,
+            human_size(UPLOAD_LIMIT),
+            e
+        ));
+        return;
+    }
+    sort_entries(&mut files, SortBy::Name);
+
+    if files.is_empty() {
+        message.say_error(format!("'{}' contains no files", path.display()));
+        return;
+    }
+
+    message.broadcast_typing();
+
+    let max_bytes = MAX_ZIP_PARTS as u64 * UPLOAD_LIMIT;
+    let mut total_bytes = 0;
+    let mut failed = Vec::new();
+    let mut entries = Vec::with_capacity(files.len());
+    for file in &files {
+        let arc_path = path.join(&file.name);
+        let entry = match convert_args {
+            Some(convert_args) => match convert_for_zip(&arc_path, &file.name, convert_args) {
+                Ok(entry) => entry,
+                // files that can't be converted are still included as-is
+                Err(e) => {
+                    let reason: String = e.lines().next().unwrap_or("").chars().take(100).collect();
+                    failed.push(format!("{}: {}", file.name, reason));
+                    ZipEntry { name: file.name.clone(), size: file.size, source: ZipSource::Arc(arc_path) }
+                }
+            },
+            None => ZipEntry { name: file.name.clone(), size: file.size, source: ZipSource::Arc(arc_path) },
         };
-        message.send_file(
-            file_name,
-            content
-        ).map_err(|e| {
+
+        // converting can grow files a lot, so the raw size checked by the walk isn't enough
+        total_bytes += entry.size;
+        if total_bytes > max_bytes {
+            message.say_error(format!(
+                "'{}' is too large to send converted ({}; over {} after converting {} of them, max {} zip parts of {} each)",
+                path.display(),
+                folder_summary(path),
+                human_size(max_bytes),
+                entries.len() + 1,
+                MAX_ZIP_PARTS,
+                human_size(UPLOAD_LIMIT)
+            ));
+            return;
+        }
+        entries.push(entry);
+    }
+
+    let folder_name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("arc"));
+    send_zip(entries, &folder_name, &format!("'{}'", path.display()), message);
+
+    if !failed.is_empty() {
+        message.say(
+            MessageBuilder::new()
+                .push(format!("{} files could not be converted and were included unconverted:", failed.len()))
+                .push_codeblock_safe(summarize_list(&failed, LISTED_FAILURES, "\n"), None)
+                .build()
+        );
+    }
+}
+
+pub fn get(s: &str, message: &MessageHelper) {
+    let (version, args) = take_version(&s[3..]);
+    let (path, rest) = match args.find(char::is_whitespace) {
+        Some(i) => (&args[..i], args[i..].trim()),
+        None => (args, ""),
+    };
+
+    let convert_args = if rest.is_empty() {
+        None
+    } else if rest == "convert" || rest.starts_with("convert ") {
+        Some(rest[7..].trim())
+    } else {
+        message.say_error("Use format 'get [@version] [file] [convert [args]]'");
+        return;
+    };
+
+    let path = match to_arc_path(version, path) {
+        Some(path) => path,
+        None => {
+            message.say_error("Invalid path");
+            return;
+        }
+    };
+
+    if path.is_dir() {
+        get_folder(&path, convert_args, message);
+        return;
+    }
+
+    if let Some(convert_args) = convert_args {
+        convert_from_arc(&path, convert_args, message);
+        return;
+    }
+
+    let _ = message.send_file(path.to_str().unwrap(), path.to_str().unwrap())
+        .map_err(|e| {
             message.say(
                 MessageBuilder::new()
-                    .push(format!("Error uploading '{}':", file_name))
+                    .push(format!("Error getting '{}':", path.to_str().unwrap()))
                     .push_codeblock_safe(e.to_string(), None)
                     .build()
             );
+        });
+}
+
+fn available_versions() -> Vec<String> {
+    let mut versions: Vec<String> = fs::read_dir(ARC_VERSIONS_ROOT)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
         })
-        .unwrap();
+        .unwrap_or_default();
+    versions.sort();
+    versions
+}
+
+pub fn arc_versions(message: &MessageHelper) {
+    let versions = available_versions();
+    let versions = if versions.is_empty() {
+        String::from("(none)")
+    } else {
+        versions.join("\n")
+    };
+
+    message.say(
+        MessageBuilder::new()
+            .push(format!("Default: {}\nVersions in {} (use as @version):", ARC_ROOT, ARC_VERSIONS_ROOT))
+            .push_codeblock_safe(versions, None)
+            .build()
+    );
+}
+
+fn file_crc(path: &Path) -> std::io::Result<u32> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; 0x10000];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+
+    Ok(hasher.finalize())
+}
+
+fn file_sizes(root: &Path) -> std::io::Result<BTreeMap<String, u64>> {
+    let mut files = Vec::new();
+    walk_files(root, root, &mut files, &mut WalkBudget::listing())?;
+    Ok(files.into_iter().map(|file| (file.name, file.size)).collect())
+}
+
+const ARC_DIFF_USAGE: &str = "Use format 'arc_diff [version] [version] <folder>', with 'arc' for the mounted /arc. \
+                              The folder can't be the arc root, it's too big to compare";
+
+/// Most bytes read to compare the contents of same-size files in one diff,
+/// counting both sides
+const MAX_DIFF_CRC_BYTES: u64 = 512 * 1024 * 1024;
+
+/// `arc` stands for the default mount, anything else is a version in `/arcs`
+fn diff_version(version: &str) -> Option<&str> {
+    match version {
+        "arc" => None,
+        version => Some(version),
+    }
+}
+
+pub fn arc_diff(s: &str, message: &MessageHelper) {
+    let args: Vec<&str> = s[8..].split_whitespace().collect();
+    let (old_version, new_version, folder) = match args.as_slice() {
+        [old, new, folder] if !folder.trim_matches('/').is_empty() => (*old, *new, *folder),
+        // no folder or the root, which the usage explains
+        _ => {
+            message.say_error(ARC_DIFF_USAGE);
+            return;
+        }
+    };
+    let old_version = old_version.trim_start_matches('@');
+    let new_version = new_version.trim_start_matches('@');
+
+    let (old_root, new_root) = match (
+        to_arc_path(diff_version(old_version), folder),
+        to_arc_path(diff_version(new_version), folder)
+    ) {
+        (Some(old_root), Some(new_root)) => (old_root, new_root),
+        _ => {
+            message.say_error(format!("Invalid version or path. {}", ARC_DIFF_USAGE));
+            return;
+        }
+    };
+
+    message.broadcast_typing();
+
+    let (old_files, new_files) = match (file_sizes(&old_root), file_sizes(&new_root)) {
+        (Ok(old_files), Ok(new_files)) => (old_files, new_files),
+        (Err(e), _) | (_, Err(e)) => {
+            message.say_error(format!(
+                "Could not list folder: {}\nAvailable versions: {}",
+                e,
+                available_versions().join(", ")
+            ));
+            return;
+        }
+    };
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut modified = Vec::new();
+    let mut crc_budget = MAX_DIFF_CRC_BYTES;
+    let mut not_compared = 0;
+
+    for (name, old_size) in &old_files {
+        match new_files.get(name) {
+            None => removed.push(format!("- {}  ({})", name, human_size(*old_size))),
+            Some(new_size) if new_size != old_size => modified.push(format!(
+                "~ {}  ({} -> {})",
+                name,
+                human_size(*old_size),
+                human_size(*new_size)
+            )),
+            // same size, so only the contents can tell
+            Some(_) if *old_size * 2 > crc_budget => not_compared += 1,
+            Some(_) => {
+                crc_budget -= *old_size * 2;
+                match (file_crc(&old_root.join(name)), file_crc(&new_root.join(name))) {
+                    (Ok(old_crc), Ok(new_crc)) if old_crc != new_crc => modified.push(format!(
+                        "~ {}  ({}, crc32 {:08x} -> {:08x})",
+                        name,
+                        human_size(*old_size),
+                        old_crc,
+                        new_crc
+                    )),
+                    (Ok(_), Ok(_)) => {}
+                    (Err(e), _) | (_, Err(e)) => modified.push(format!("? {}  (could not read: {})", name, e)),
+                }
+            }
+        }
+    }
+    for (name, new_size) in &new_files {
+        if !old_files.contains_key(name) {
+            added.push(format!("+ {}  ({})", name, human_size(*new_size)));
+        }
+    }
+
+    let mut summary = format!(
+        "{} -> {} in /{}: {} added, {} removed, {} modified",
+        old_version,
+        new_version,
+        folder.trim_start_matches('/'),
+        added.len(),
+        removed.len(),
+        modified.len()
+    );
+    if not_compared > 0 {
+        summary.push_str(&format!(
+            ", {} files of the same size not compared (over {} of reads, pick a smaller folder to check them)",
+            not_compared,
+            human_size(MAX_DIFF_CRC_BYTES)
+        ));
+    }
+
+    let report: Vec<String> = added.into_iter()
+        .chain(removed)
+        .chain(modified)
+        .collect();
+
+    if report.is_empty() {
+        message.say(format!("{}\nNo differences found", summary));
+    } else if report.len() <= NUM_LINES {
+        message.say(
+            MessageBuilder::new()
+                .push(summary)
+                .push_codeblock_safe(report.join("\n"), None)
+                .build()
+        );
+    } else {
+        let report = format!("{}\n\n{}\n", summary, report.join("\n"));
+        let file_name = format!("arc_diff_{}_{}.txt", old_version, new_version);
+        let _ = message.send_file((report.as_bytes(), &file_name[..]), summary)
+            .map_err(|e| {
+                message.say_error(format!("Error uploading diff: {}", e));
+            });
     }
 }
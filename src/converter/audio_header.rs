@@ -0,0 +1,216 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use nus3audio::Nus3audioFile;
+
+/// Codec of a track embedded in a nus3audio
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// Switch opus with namco's "OPUS" header, little endian
+    Opus,
+    /// Namco's DSP ADPCM container, big endian
+    Idsp,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Opus => "lopus",
+            Encoding::Idsp => "idsp",
+        }
+    }
+}
+
+/// Magics of the Switch opus header and data chunks wrapped by namco's header
+const SWITCH_OPUS_HEADER: u32 = 0x8000_0001;
+const SWITCH_OPUS_DATA: u32 = 0x8000_0004;
+/// How far into a track to look for the Switch opus header when neither
+/// offset field points at it
+const OPUS_HEADER_SEARCH: usize = 0x100;
+
+pub struct AudioHeader {
+    pub encoding: Encoding,
+    pub channels: u32,
+    pub sample_rate: u32,
+    pub sample_count: u32,
+    /// Loop start and end in samples, if the track loops
+    pub loop_points: Option<(u32, u32)>,
+}
+
+impl AudioHeader {
+    /// Read the header at the start of a nus3audio track, `None` if it isn't
+    /// a format we know
+    pub fn parse(data: &[u8]) -> Option<AudioHeader> {
+        if data.len() < 0x20 {
+            return None;
+        }
+
+        let (encoding, sample_count, channels, sample_rate, loop_start, loop_end) = match &data[..4] {
+            b"OPUS" => {
+                // namco's header only wraps the Switch opus header, which holds
+                // the real channel count and sample rate
+                let header = find_switch_opus_header(data)?;
+                let sample_rate = LittleEndian::read_u32(&data[header + 0xC..]);
+                let sample_count = count_opus_samples(data, header, sample_rate)
+                    .unwrap_or_else(|| LittleEndian::read_u32(&data[0x8..]));
+                // the loop is in namco's header, unless the Switch header starts before it
+                let (loop_start, loop_end) = if header >= 0x1C {
+                    (LittleEndian::read_u32(&data[0x14..]), LittleEndian::read_u32(&data[0x18..]))
+                } else {
+                    (0, 0)
+                };
+                (Encoding::Opus, sample_count, data[header + 9] as u32, sample_rate, loop_start, loop_end)
+            }
+            b"IDSP" => {
+                let field = |offset: usize| BigEndian::read_u32(&data[offset..offset + 4]);
+                (Encoding::Idsp, field(0x10), field(0x8), field(0xC), field(0x14), field(0x18))
+            }
+            _ => return None,
+        };
+
+        if sample_rate == 0 {
+            return None;
+        }
+
+        Some(AudioHeader {
+            encoding,
+            channels,
+            sample_rate,
+            sample_count,
+            loop_points: if loop_end > loop_start && loop_end <= sample_count {
+                Some((loop_start, loop_end))
+            } else {
+                None
+            },
+        })
+    }
+
+    pub fn seconds(&self, samples: u32) -> f64 {
+        samples as f64 / self.sample_rate as f64
+    }
+
+    /// Average bitrate in bits per second of a track whose data is `data_len` bytes
+    pub fn bitrate(&self, data_len: usize) -> u32 {
+        let seconds = self.seconds(self.sample_count);
+        if seconds > 0.0 {
+            (data_len as f64 * 8.0 / seconds) as u32
+        } else {
+            0
+        }
+    }
+
+    /// Format a sample position as `m:ss.sss`
+    pub fn timestamp(&self, samples: u32) -> String {
+        let millis = samples as u64 * 1000 / self.sample_rate as u64;
+        format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+    }
+}
+
+/// Offset of the Switch opus header inside a namco opus track. The u32 at
+/// 0xC points to it, files from some tools point to it from 0x20 instead, and
+/// failing both the start of the track is searched for its magic.
+fn find_switch_opus_header(data: &[u8]) -> Option<usize> {
+    let is_header = |offset: usize| {
+        offset + 0x20 <= data.len() && LittleEndian::read_u32(&data[offset..]) == SWITCH_OPUS_HEADER
+    };
+
+    [0xC, 0x20]
+        .iter()
+        .filter(|&&field| field + 4 <= data.len())
+        .map(|&field| LittleEndian::read_u32(&data[field..]) as usize)
+        .chain((0x10..OPUS_HEADER_SEARCH).step_by(4))
+        .find(|&offset| is_header(offset))
+}
+
+/// Samples in a track, counted from the opus packets in its data chunk since
+/// namco's header doesn't reliably store it. Each packet is a big endian size,
+/// the encoder's final range, then the packet itself.
+fn count_opus_samples(data: &[u8], header: usize, sample_rate: u32) -> Option<u32> {
+    let chunk = header.checked_add(LittleEndian::read_u32(&data[header + 0x10..]) as usize)?;
+    if LittleEndian::read_u32(data.get(chunk..chunk + 4)?) != SWITCH_OPUS_DATA {
+        return None;
+    }
+    let size = LittleEndian::read_u32(data.get(chunk + 4..chunk + 8)?) as usize;
+    let end = (chunk + 8).checked_add(size)?.min(data.len());
+
+    let mut offset = chunk + 8;
+    let mut samples = 0u64;
+    while offset + 8 < end {
+        let packet_size = BigEndian::read_u32(&data[offset..]) as usize;
+        let packet = data.get(offset + 8..(offset + 8).checked_add(packet_size)?)?;
+        samples += opus_packet_samples(packet)? as u64;
+        offset += 8 + packet_size;
+    }
+
+    // opus always decodes at 48 kHz
+    Some((samples * sample_rate as u64 / 48000) as u32)
+}
+
+/// Samples at 48 kHz in one opus packet, from its TOC byte (RFC 6716 3.1)
+fn opus_packet_samples(packet: &[u8]) -> Option<u32> {
+    let toc = *packet.first()?;
+    let config = (toc >> 3) as usize;
+    // frame length in 2.5ms units: SILK, then hybrid, then CELT configurations
+    let frame_length = match config {
+        0..=11 => [4, 8, 16, 24][config & 3],
+        12..=15 => [4, 8][config & 1],
+        _ => [1, 2, 4, 8][config & 3],
+    };
+    let frames = match toc & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1)? & 0x3F) as u32,
+    };
+
+    Some(frames * frame_length * 120)
+}
+
+/// Parse a nus3audio, checking its section table first since the nus3audio
+/// crate panics on truncated or malformed files
+pub fn read_nus3audio(data: &[u8]) -> Result<Nus3audioFile, String> {
+    check_nus3audio(data)?;
+    Ok(Nus3audioFile::from_bytes(data))
+}
+
+fn check_nus3audio(data: &[u8]) -> Result<(), String> {
+    let read_u32 = |offset: usize| data.get(offset..offset + 4).map(LittleEndian::read_u32);
+    if data.len() < 0x18 || &data[..4] != b"NUS3" || &data[0x8..0x10] != b"AUDIINDX" {
+        return Err(String::from("Not a nus3audio file"));
+    }
+    let count = read_u32(0x14).unwrap() as usize;
+
+    // every section is a magic, a u32 size and then its data
+    let mut sections = Vec::new();
+    let mut offset = 0x14 + read_u32(0x10).unwrap() as usize;
+    while offset + 8 <= data.len() {
+        let size = read_u32(offset + 4).unwrap() as usize;
+        let start = offset + 8;
+        let end = start.checked_add(size)
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| format!("Section at {:#x} runs past the end of the file", offset))?;
+        sections.push((&data[offset..offset + 4], start, end));
+        offset = end;
+    }
+    let section = |magic: &[u8], entry_size: usize| {
+        sections.iter()
+            .find(|(section_magic, _, _)| *section_magic == magic)
+            .filter(|(_, start, end)| count.checked_mul(entry_size).map_or(false, |size| size <= end - start))
+            .map(|&(_, start, _)| start)
+            .ok_or_else(|| format!("Missing or short {} section", String::from_utf8_lossy(magic)))
+    };
+
+    section(b"TNID", 4)?;
+    let names = section(b"NMOF", 4)?;
+    let files = section(b"ADOF", 8)?;
+    for i in 0..count {
+        let name = read_u32(names + i * 4).unwrap() as usize;
+        if !data.get(name..).map_or(false, |name| name.contains(&0)) {
+            return Err(format!("Track {} has a name outside of the file", i));
+        }
+
+        let (file_offset, file_size) = (read_u32(files + i * 8).unwrap(), read_u32(files + i * 8 + 4).unwrap());
+        if file_offset as u64 + file_size as u64 > data.len() as u64 {
+            return Err(format!("Track {} runs past the end of the file", i));
+        }
+    }
+
+    Ok(())
+}
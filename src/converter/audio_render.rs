@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use hound::{SampleFormat, WavReader, WavWriter};
+
+use super::error::ConvertError;
+
+/// Rewrite a wav in place so it plays through the loop `loops` times, then
+/// fades out over `fade_seconds` while continuing to loop
+pub fn render_loops(path: &Path, loop_points: (u32, u32), loops: u32, fade_seconds: f64) -> Result<(), ConvertError> {
+    let (spec, samples) = {
+        let mut reader = WavReader::open(path)?;
+        let spec = reader.spec();
+        if spec.sample_format != SampleFormat::Int || spec.bits_per_sample != 16 {
+            return Err(ConvertError::nus3audio("Only 16-bit wavs can be looped"));
+        }
+        let samples = reader.samples::<i16>().collect::<Result<Vec<_>, _>>()?;
+        (spec, samples)
+    };
+
+    let channels = spec.channels as usize;
+    let frames = samples.len() / channels;
+    let start = loop_points.0 as usize;
+    let end = (loop_points.1 as usize).min(frames);
+    if start >= end {
+        return Err(ConvertError::nus3audio(&format!(
+            "Bad loop points {}-{}, there are only {} samples", loop_points.0, loop_points.1, frames
+        )));
+    }
+
+    let frame = |i: usize| &samples[i * channels..(i + 1) * channels];
+    let fade_frames = (fade_seconds * spec.sample_rate as f64) as usize;
+
+    let mut writer = WavWriter::create(path, spec)?;
+    for i in 0..end {
+        for &sample in frame(i) {
+            writer.write_sample(sample)?;
+        }
+    }
+    for _ in 1..loops {
+        for i in start..end {
+            for &sample in frame(i) {
+                writer.write_sample(sample)?;
+            }
+        }
+    }
+    for i in 0..fade_frames {
+        let gain = 1.0 - (i as f64 / fade_frames as f64);
+        for &sample in frame(start + i % (end - start)) {
+            writer.write_sample((sample as f64 * gain) as i16)?;
+        }
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+/// Static ffmpeg build installed by update.sh
+pub const FFMPEG_PATH: &str = "ffmpeg/ffmpeg";
+
+/// Encode a wav to ogg or flac with ffmpeg. For ogg, `quality` is the vorbis
+/// quality level (0-10).
+pub fn encode(path: &Path, format: &str, quality: u32) -> Result<PathBuf, ConvertError> {
+    let mut outpath = PathBuf::from(path);
+    outpath.set_extension(format);
+
+    let mut command = Command::new(FFMPEG_PATH);
+    command
+        .arg("-y")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-i")
+        .arg(path);
+
+    match format {
+        "ogg" => command.arg("-c:a").arg("libvorbis").arg("-q:a").arg(quality.to_string()),
+        "flac" => command.arg("-c:a").arg("flac"),
+        _ => return Err(ConvertError::bad_extension()),
+    };
+
+    let out = command.arg(&outpath).output()?;
+    if !out.status.success() || !outpath.exists() {
+        return Err(ConvertError::file(std::str::from_utf8(&out.stderr[..])?));
+    }
+
+    Ok(outpath)
+}
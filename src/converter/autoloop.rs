@@ -0,0 +1,106 @@
+/// Samples per analysis block, about 21ms at 48 kHz
+const BLOCK: usize = 1024;
+/// How long the audio has to match before both loop points
+const MATCH_SECONDS: f64 = 8.0;
+/// Shortest loop considered, shorter repeats are usually just the beat
+const MIN_LOOP_SECONDS: f64 = 10.0;
+/// Samples either side of the loop points compared when lining up the waveforms
+const ALIGN_WINDOW: usize = 2048;
+
+pub struct FoundLoop {
+    pub start: usize,
+    pub end: usize,
+    /// Correlation of the audio around the two loop points, 1.0 is identical
+    pub similarity: f64,
+}
+
+/// Loudness and brightness (loudness of the first difference) of each block,
+/// roughly what the ear notices jumping at a bad loop point
+fn block_features(mono: &[f32]) -> Vec<[f32; 2]> {
+    mono.chunks(BLOCK)
+        .map(|block| {
+            let len = block.len() as f32;
+            let rms = (block.iter().map(|s| s * s).sum::<f32>() / len).sqrt();
+            let brightness = (block.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum::<f32>() / len).sqrt();
+            [rms, brightness]
+        })
+        .collect()
+}
+
+/// Normalized cross-correlation of two equally long slices
+fn correlation(a: &[f32], b: &[f32]) -> f64 {
+    let (mut ab, mut aa, mut bb) = (0.0f64, 0.0f64, 0.0f64);
+    for (&a, &b) in a.iter().zip(b) {
+        ab += a as f64 * b as f64;
+        aa += a as f64 * a as f64;
+        bb += b as f64 * b as f64;
+    }
+    if aa == 0.0 || bb == 0.0 {
+        0.0
+    } else {
+        ab / (aa * bb).sqrt()
+    }
+}
+
+/// Find the loop where the music repeats itself most closely. The loop length
+/// comes from comparing block features at every lag, then the end is moved
+/// sample by sample to line its waveform up with the start's.
+pub fn find_loop(mono: &[f32], sample_rate: u32) -> Option<FoundLoop> {
+    let features = block_features(mono);
+    let n = features.len();
+    let blocks_per_second = sample_rate as f64 / BLOCK as f64;
+    let window = (MATCH_SECONDS * blocks_per_second) as usize;
+    let min_lag = ((MIN_LOOP_SECONDS * blocks_per_second) as usize).max(n / 4);
+    if window == 0 || n < min_lag + window {
+        return None;
+    }
+
+    let mean_rms = features.iter().map(|f| f[0]).sum::<f32>() / n as f32;
+    // silence matches silence perfectly, so quiet stretches can't be loop points
+    let loud: Vec<bool> = features.iter().map(|f| f[0] > mean_rms * 0.1).collect();
+
+    // (difference, lag, first block of the matching window)
+    let mut best: Option<(f32, usize, usize)> = None;
+    for lag in min_lag..=(n - window) {
+        let difference = |i: usize| {
+            let (a, b) = (features[i], features[i + lag]);
+            ((a[0] - b[0]).abs() + (a[1] - b[1]).abs(), a[0] + b[0] + a[1] + b[1])
+        };
+
+        let (mut diff_sum, mut total_sum, mut loud_count) = (0.0, 0.0, 0);
+        for i in 0..n - lag {
+            let (diff, total) = difference(i);
+            diff_sum += diff;
+            total_sum += total;
+            loud_count += (loud[i] && loud[i + lag]) as usize;
+            if i >= window {
+                let (diff, total) = difference(i - window);
+                diff_sum -= diff;
+                total_sum -= total;
+                loud_count -= (loud[i - window] && loud[i - window + lag]) as usize;
+            }
+
+            if i + 1 >= window && loud_count * 5 >= window * 4 && total_sum > 0.0 {
+                let score = diff_sum / total_sum;
+                if best.map_or(true, |(best_score, _, _)| score < best_score) {
+                    best = Some((score, lag, i + 1 - window));
+                }
+            }
+        }
+    }
+
+    let (_, lag, window_start) = best?;
+    let start = (window_start + window / 2) * BLOCK;
+    let end = start + lag * BLOCK;
+
+    if start < ALIGN_WINDOW || end + BLOCK + ALIGN_WINDOW > mono.len() {
+        return Some(FoundLoop { start, end: end.min(mono.len()), similarity: 0.0 });
+    }
+
+    let around_start = &mono[start - ALIGN_WINDOW..start + ALIGN_WINDOW];
+    let (end, similarity) = (end - BLOCK..=end + BLOCK)
+        .map(|end| (end, correlation(around_start, &mono[end - ALIGN_WINDOW..end + ALIGN_WINDOW])))
+        .fold((end, std::f64::MIN), |best, candidate| if candidate.1 > best.1 { candidate } else { best });
+
+    Some(FoundLoop { start, end, similarity })
+}
@@ -26,7 +26,7 @@ impl Converter for BymlConverter {
         }
     }
 
-    fn convert_from(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_from(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("yml");
         let out = Command::new("byml_to_yml")
@@ -45,7 +45,7 @@ impl Converter for BymlConverter {
         }
     }
 
-    fn convert_to(&self, path: &Path, message: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_to(&self, path: &Path, message: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let little_endian = match message {
             Some(s) if s.contains("wiiu") | s.contains("wii u") | s.contains("Wii U")
                         | s.contains("wii U") | s.contains("big") => false,
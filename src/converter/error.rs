@@ -12,7 +12,7 @@ impl fmt::Debug for ConvertError {
     }
 }
 
-pub static SUPPORTED_TYPES: &str = "prc, xml, wav, lopus, nus3audio, dds, mscsb, c, sqb, yaml, numatb, stprm, stdat, motion_list.bin, zip, sarc, szs, pack, bactorpack, bmodelsh, beventpack, stera, stats, arc, bars, blarc, bgenv, genvb, (s)baischedule, (s)baniminfo, (s)bgdata, (s)bgsvdata, (s)bquestpack, (s)bquestpack, (s)byml, mubin, yml";
+pub static SUPPORTED_TYPES: &str = "prc, xml, wav, mp3, ogg, flac, aiff, lopus, nus3audio, nus3bank, dds, mscsb, c, sqb, yaml, numatb, stprm, stdat, motion_list.bin, zip, sarc, szs, pack, bactorpack, bmodelsh, beventpack, stera, stats, arc, bars, blarc, bgenv, genvb, (s)baischedule, (s)baniminfo, (s)bgdata, (s)bgsvdata, (s)bquestpack, (s)bquestpack, (s)byml, mubin, yml";
 
 impl ConvertError {
     pub fn bad_extension() -> ConvertError {
@@ -43,6 +43,13 @@ impl ConvertError {
         }
     }
 
+    pub fn nus3bank(message: &str) -> ConvertError {
+        ConvertError {
+            message: message.to_string(),
+            kind: ConvertErrorKind::Nus3bank
+        }
+    }
+
     pub fn msc(message: &str) -> ConvertError {
         ConvertError {
             message: message.to_string(),
@@ -128,6 +135,15 @@ impl std::convert::From<samplerate::Error> for ConvertError {
     }
 }
 
+impl std::convert::From<symphonia::core::errors::Error> for ConvertError {
+    fn from(err: symphonia::core::errors::Error) -> Self {
+        ConvertError {
+            message: format!("DecodeError: {}", err),
+            kind: ConvertErrorKind::Nus3audio,
+        }
+    }
+}
+
 impl std::convert::From<sarc::parser::Error> for ConvertError {
     fn from(err: sarc::parser::Error) -> Self {
         ConvertError {
@@ -169,6 +185,7 @@ pub enum ConvertErrorKind {
     BadExtension,
     Param,
     Nus3audio,
+    Nus3bank,
     Msc,
     File,
     HandleNone,
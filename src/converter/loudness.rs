@@ -0,0 +1,141 @@
+//! Loudness measurement following EBU R128 / ITU-R BS.1770, for 48 kHz audio
+
+/// Two stage K-weighting filter from BS.1770, as (b, a) biquad coefficients
+/// for 48 kHz: a high shelf for the head's acoustic effect, then a high pass
+const K_WEIGHTING: [([f64; 3], [f64; 3]); 2] = [
+    (
+        [1.53512485958697, -2.69169618940638, 1.19839281085285],
+        [1.0, -1.69065929318241, 0.73248077421585],
+    ),
+    (
+        [1.0, -2.0, 1.0],
+        [1.0, -1.99004745483398, 0.99007225036621],
+    ),
+];
+
+const SAMPLE_RATE: usize = 48000;
+/// Gating blocks are 400ms long and start every 100ms
+const BLOCK_SAMPLES: usize = SAMPLE_RATE * 400 / 1000;
+const STEP_SAMPLES: usize = SAMPLE_RATE * 100 / 1000;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Oversampling factor and taps per phase of the true peak interpolation filter
+const OVERSAMPLE: usize = 4;
+const PEAK_TAPS: usize = 12;
+
+fn biquad(samples: &mut [f64], (b, a): ([f64; 3], [f64; 3])) {
+    let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+    for sample in samples.iter_mut() {
+        let x = *sample;
+        let y = b[0] * x + b[1] * x1 + b[2] * x2 - a[1] * y1 - a[2] * y2;
+        x2 = x1;
+        x1 = x;
+        y2 = y1;
+        y1 = y;
+        *sample = y;
+    }
+}
+
+fn block_loudness(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Integrated loudness in LUFS of interleaved 48 kHz samples, `None` if the
+/// audio is too short or too quiet to measure
+pub fn integrated_loudness(samples: &[f32], channels: usize) -> Option<f64> {
+    let frames = samples.len() / channels;
+    if frames < BLOCK_SAMPLES {
+        return None;
+    }
+
+    // running sums of the K-weighted power, summed over channels (all weighted 1.0)
+    let mut power_sums = vec![0.0f64; frames + 1];
+    for channel in 0..channels {
+        let mut weighted: Vec<f64> = samples
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .map(|&sample| sample as f64)
+            .collect();
+        for &stage in &K_WEIGHTING {
+            biquad(&mut weighted, stage);
+        }
+        let mut sum = 0.0;
+        for (i, sample) in weighted.iter().enumerate() {
+            sum += sample * sample;
+            power_sums[i + 1] += sum;
+        }
+    }
+
+    let blocks: Vec<f64> = (0..=(frames - BLOCK_SAMPLES) / STEP_SAMPLES)
+        .map(|i| {
+            let start = i * STEP_SAMPLES;
+            (power_sums[start + BLOCK_SAMPLES] - power_sums[start]) / BLOCK_SAMPLES as f64
+        })
+        .filter(|&power| power > 0.0 && block_loudness(power) > ABSOLUTE_GATE)
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let relative_gate = block_loudness(blocks.iter().sum::<f64>() / blocks.len() as f64) + RELATIVE_GATE;
+    let gated: Vec<f64> = blocks
+        .into_iter()
+        .filter(|&power| block_loudness(power) > relative_gate)
+        .collect();
+
+    Some(block_loudness(gated.iter().sum::<f64>() / gated.len() as f64))
+}
+
+/// Hann windowed sinc filter for each fractional offset of the oversampling
+fn interpolation_phases() -> Vec<Vec<f64>> {
+    (0..OVERSAMPLE)
+        .map(|phase| {
+            let offset = phase as f64 / OVERSAMPLE as f64;
+            (0..PEAK_TAPS)
+                .map(|tap| {
+                    let x = tap as f64 - (PEAK_TAPS / 2) as f64 + 1.0 - offset;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+                    };
+                    let window_x = (tap as f64 + 1.0 - offset) / PEAK_TAPS as f64;
+                    let window = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * window_x).cos();
+                    sinc * window
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Estimated true peak (as linear amplitude) from 4x oversampling, which
+/// catches the overs between samples that a sample peak misses
+pub fn true_peak(samples: &[f32], channels: usize) -> f64 {
+    let phases = interpolation_phases();
+    let mut peak = 0.0f64;
+    for channel in 0..channels {
+        let channel_samples: Vec<f64> = samples
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .map(|&sample| sample as f64)
+            .collect();
+        for window in channel_samples.windows(PEAK_TAPS) {
+            for taps in &phases {
+                let value: f64 = window.iter().zip(taps).map(|(sample, tap)| sample * tap).sum();
+                peak = peak.max(value.abs());
+            }
+        }
+        for &sample in &channel_samples {
+            peak = peak.max(sample.abs());
+        }
+    }
+
+    peak
+}
+
+pub fn to_db(amplitude: f64) -> f64 {
+    20.0 * amplitude.log10()
+}
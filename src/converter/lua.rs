@@ -14,7 +14,7 @@ impl Converter for LuaConverter {
         }
     }
 
-    fn convert_from(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_from(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("lua");
         let out = Command::new("dotnet")
@@ -31,7 +31,7 @@ impl Converter for LuaConverter {
         } 
     }
 
-    fn convert_to(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_to(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("lc");
         let out = Command::new("luac")
@@ -2,11 +2,16 @@ mod lua;
 mod msc;
 mod sqb;
 pub mod byml;
+pub mod audio_header;
+pub mod audio_render;
+mod autoloop;
+mod loudness;
 mod error;
 mod param;
 mod numatb;
 mod motion_list;
 mod nus3audio_convert;
+mod nus3bank;
 mod nutexb;
 mod sarc_converter;
 use error::ConvertError;
@@ -16,6 +21,7 @@ use std::ffi::OsStr;
 static CONVERTERS: &[&dyn Converter] = &[
     &msc::MscsbConverter,
     &nus3audio_convert::Nus3audioConverter,
+    &nus3bank::Nus3bankConverter,
     &param::ParamConverter,
     &motion_list::MotionListConverter,
     &sqb::SqbConverter,
@@ -35,6 +41,25 @@ pub fn extension<'a>(path: &'a Path) -> &'a str {
         .unwrap()
 }
 
+/// Discord's attachment size limit for servers without boosts
+pub const UPLOAD_LIMIT: u64 = 8 * 1024 * 1024;
+
+/// Size in the largest binary unit that keeps it above 1, e.g. `4.2 MiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
 fn as_non_empty_string(string: &str) -> Option<&str> {
     match string {
         "" => None,
@@ -42,16 +67,26 @@ fn as_non_empty_string(string: &str) -> Option<&str> {
     }
 }
 
-pub fn convert<P: AsRef<Path>>(path: P, message: &str) -> Result<PathBuf, ConvertError> {
+/// Convert the file at `path`, returning the new file along with things the
+/// user should know about the conversion, like where its loop points came from
+pub fn convert<P: AsRef<Path>>(path: P, message: &str) -> Result<(PathBuf, Vec<String>), ConvertError> {
     let path = path.as_ref();
-    let ext = extension(path);
+    // bank ymls look like any other yml, so they're told apart once here
+    // rather than by every converter that takes ymls
+    let ext = match extension(path) {
+        "yml" | "yaml" if nus3bank::is_bank_yaml(path) => nus3bank::BANK_YAML_EXTENSION,
+        ext => ext,
+    };
     let message = as_non_empty_string(message);
+    let mut notes = Vec::new();
     let return_path = 'ret_path: {
         let mut last_err = None;
         for converter in CONVERTERS {
+            // a failed converter's notes don't apply to the one that succeeds
+            notes.clear();
             match match converter.get_conversion(ext, path) {
-                Convert::To => converter.convert_to(path, message),
-                Convert::From => converter.convert_from(path, message),
+                Convert::To => converter.convert_to(path, message, &mut notes),
+                Convert::From => converter.convert_from(path, message, &mut notes),
                 Convert::None => continue
             } {
                 return_path @ Ok(_) => break 'ret_path return_path,
@@ -65,7 +100,7 @@ pub fn convert<P: AsRef<Path>>(path: P, message: &str) -> Result<PathBuf, Conver
     std::fs::remove_file(path)?;
     
     if return_path.exists() {
-        Ok(return_path)
+        Ok((return_path, notes))
     } else {
         Err(ConvertError::file("Returned file not found"))
     }
@@ -79,6 +114,6 @@ enum Convert {
 
 trait Converter: Sync {
     fn get_conversion(&self, file_extension: &str, path: &Path) -> Convert;
-    fn convert_to(&self, path: &Path, message: Option<&str>) -> Result<PathBuf, ConvertError>;
-    fn convert_from(&self, path: &Path, message: Option<&str>) -> Result<PathBuf, ConvertError>;
+    fn convert_to(&self, path: &Path, message: Option<&str>, notes: &mut Vec<String>) -> Result<PathBuf, ConvertError>;
+    fn convert_from(&self, path: &Path, message: Option<&str>, notes: &mut Vec<String>) -> Result<PathBuf, ConvertError>;
 }
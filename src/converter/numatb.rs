@@ -14,7 +14,7 @@ impl Converter for MaterialConverter {
         }
     }
 
-    fn convert_from(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_from(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("xml");
         let out = Command::new("dotnet")
@@ -30,7 +30,7 @@ impl Converter for MaterialConverter {
         } 
     }
 
-    fn convert_to(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_to(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("numatb");
         let out = Command::new("dotnet")
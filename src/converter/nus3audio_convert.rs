@@ -1,15 +1,30 @@
 use std::fs;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::ops::Range;
 use std::str::FromStr;
 use std::num::ParseFloatError;
 
-use hound::WavReader;
+use byteorder::{ByteOrder, LittleEndian};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use samplerate::ConverterType;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, Tag};
+use symphonia::core::probe::Hint;
 use nus3audio::{AudioFile, Nus3audioFile};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::FileOptions};
 
+use super::audio_header::read_nus3audio;
+use super::autoloop;
+use super::loudness;
+use super::nus3bank::Nus3bank;
 use super::error::ConvertError;
-use super::{Converter, Convert};
+use super::{Converter, Convert, human_size, UPLOAD_LIMIT};
 
 pub struct Nus3audioConverter;
 
@@ -49,7 +64,9 @@ pub fn message_to_range(message: &str, num_samples: usize, conversion_rate: f64)
         .map_err(|_| ConvertError::message_format(FORMAT_ERROR))?;
 
     if let &[start, end] = &bounds[..] {
-        if end <= num_samples {
+        if start >= end {
+            Err(ConvertError::message_format("Bad loop points. The loop has to end after it starts"))
+        } else if end <= num_samples {
             Ok(start..end)
         } else {
             Err(ConvertError::nus3audio(&format!(
@@ -63,127 +80,985 @@ pub fn message_to_range(message: &str, num_samples: usize, conversion_rate: f64)
     }
 }
 
-fn resample_wav(path: &Path) -> Result<(), ConvertError> {
-    let output = Command::new("python3")
-        .env("LIBROSA_CACHE_DIR", "/tmp/librosa_cache")
-        .env("NUMBA_CACHE_DIR", "/tmp/numba_cache")
-        .arg("resample.py")
-        .arg(path)
-        .output()?;
-    if !output.status.success() {
-        return Err(ConvertError::nus3audio(
-            &(
-                String::new() +
-                std::str::from_utf8(&output.stdout)? +
-                std::str::from_utf8(&output.stderr)?
-            )
-        ))
+/// Options for wav -> nus3audio conversions, given in the message around the
+/// loop range, e.g. `mono normalize=-14 bitrate=96k vbr 0:10.5-2:30`
+struct EncodeOptions {
+    loop_range: Option<String>,
+    mono: bool,
+    autoloop: bool,
+    /// Integrated loudness to normalize to, in LUFS
+    normalize: Option<f64>,
+    /// Opus bitrate in bits per second
+    bitrate: u32,
+    /// Constant rather than variable bitrate
+    cbr: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            loop_range: None,
+            mono: false,
+            autoloop: false,
+            normalize: None,
+            bitrate: DEFAULT_BITRATE,
+            cbr: true,
+        }
+    }
+}
+
+const DEFAULT_BITRATE: u32 = 64000;
+const MIN_BITRATE: u32 = 16000;
+const MAX_BITRATE: u32 = 256000;
+
+/// Loudness `normalize` aims for without a target, meant to sit close to the
+/// game's own music. `normalize=<LUFS>` matches something else.
+const DEFAULT_LOUDNESS: f64 = -16.0;
+const LOUDNESS_RANGE: Range<f64> = -40.0..-5.0;
+/// Gain is held back so the true peak stays under this, in dBTP
+const TRUE_PEAK_CEILING: f64 = -1.0;
+
+impl EncodeOptions {
+    fn parse(message: Option<&str>) -> Result<Self, ConvertError> {
+        let mut options = EncodeOptions::default();
+        let mut loop_range = Vec::new();
+        for token in message.unwrap_or("").split_whitespace() {
+            match token {
+                "mono" => options.mono = true,
+                "autoloop" => options.autoloop = true,
+                "normalize" => options.normalize = Some(DEFAULT_LOUDNESS),
+                token if token.starts_with("normalize=") => {
+                    let target = f64::from_str(&token["normalize=".len()..])
+                        .ok()
+                        .filter(|target| LOUDNESS_RANGE.contains(target))
+                        .ok_or_else(|| ConvertError::message_format(&format!(
+                            "Bad loudness target '{}'. Use normalize=<LUFS> with a value from {} to {}, e.g. normalize={}",
+                            token, LOUDNESS_RANGE.start, LOUDNESS_RANGE.end, DEFAULT_LOUDNESS
+                        )))?;
+                    options.normalize = Some(target);
+                }
+                "cbr" => options.cbr = true,
+                "vbr" => options.cbr = false,
+                token if token.starts_with("bitrate=") => {
+                    let value = &token["bitrate=".len()..];
+                    let bitrate = if value.ends_with('k') {
+                        u32::from_str(&value[..value.len() - 1]).ok().and_then(|kbps| kbps.checked_mul(1000))
+                    } else {
+                        u32::from_str(value).ok()
+                    };
+                    options.bitrate = bitrate
+                        .filter(|bitrate| (MIN_BITRATE..=MAX_BITRATE).contains(bitrate))
+                        .ok_or_else(|| ConvertError::message_format(&format!(
+                            "Bad bitrate '{}'. Use bitrate=<bits per second> from {} to {}, e.g. bitrate=96000 or bitrate=96k",
+                            token, MIN_BITRATE, MAX_BITRATE
+                        )))?;
+                }
+                token => loop_range.push(token),
+            }
+        }
+        if !loop_range.is_empty() {
+            options.loop_range = Some(loop_range.join(" "));
+        }
+
+        Ok(options)
+    }
+}
+
+/// Sample rate VGAudio's lopus encoder and the game expect
+const TARGET_RATE: u32 = 48000;
+
+/// Extensions decoded with symphonia before going through the wav pipeline
+const DECODED_EXTENSIONS: &[&str] = &["mp3", "ogg", "flac", "aiff", "aif"];
+
+/// Interleaved samples in [-1, 1]
+struct DecodedAudio {
+    sample_rate: u32,
+    channels: usize,
+    samples: Vec<f32>,
+    /// Loop stored in the file itself, in the file's own samples
+    embedded_loop: Option<EmbeddedLoop>,
+}
+
+struct EmbeddedLoop {
+    range: Range<usize>,
+    source: &'static str,
+}
+
+/// The first loop of a wav's `smpl` chunk, which most audio editors write
+fn read_smpl_loop(path: &Path) -> Option<Range<usize>> {
+    let data = fs::read(path).ok()?;
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let size = LittleEndian::read_u32(&data[offset + 4..offset + 8]) as usize;
+        let chunk = &data[offset + 8..data.len().min(offset + 8 + size)];
+        if id == b"smpl" && chunk.len() >= 0x24 + 0x18 && LittleEndian::read_u32(&chunk[0x1C..]) > 0 {
+            // the loop's end is inclusive
+            let start = LittleEndian::read_u32(&chunk[0x24 + 0x8..]) as usize;
+            let end = LittleEndian::read_u32(&chunk[0x24 + 0xC..]) as usize + 1;
+            return Some(start..end).filter(|range| range.end > range.start);
+        }
+        // chunks are padded to an even size
+        offset += 8 + size + (size & 1);
+    }
+
+    None
+}
+
+/// Loop from `LOOPSTART` and `LOOPLENGTH`/`LOOPEND` tags, as used in ogg files
+fn tag_loop(tags: &[Tag]) -> Option<Range<usize>> {
+    let value = |key: &str| {
+        tags.iter()
+            .find(|tag| tag.key.eq_ignore_ascii_case(key))
+            .and_then(|tag| tag.value.to_string().trim().parse::<usize>().ok())
+    };
+
+    let start = value("LOOPSTART")?;
+    let end = value("LOOPLENGTH")
+        .map(|length| start + length)
+        .or_else(|| value("LOOPEND"))?;
+
+    Some(start..end).filter(|range| range.end > range.start)
+}
+
+/// Read every sample of a wav as floats, whatever its sample format
+fn read_wav_samples(path: &Path) -> Result<(WavSpec, DecodedAudio), ConvertError> {
+    let mut wav = WavReader::open(path)?;
+    let spec = wav.spec();
+    let samples = match spec.sample_format {
+        SampleFormat::Float => wav.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f32;
+            wav.samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    Ok((spec, DecodedAudio {
+        sample_rate: spec.sample_rate,
+        channels: spec.channels as usize,
+        samples,
+        embedded_loop: read_smpl_loop(path).map(|range| EmbeddedLoop { range, source: "wav smpl chunk" }),
+    }))
+}
+
+/// Decode the first audio track of an mp3, ogg, flac or aiff file
+fn decode_audio(path: &Path) -> Result<DecodedAudio, ConvertError> {
+    let stream = MediaSourceStream::new(Box::new(fs::File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut format = probed.format;
+
+    // tags can be in the container (ogg comments) or ahead of it (id3)
+    let mut tags = Vec::new();
+    if let Some(metadata) = format.metadata().current() {
+        tags.extend_from_slice(metadata.tags());
+    }
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(metadata) = metadata.current() {
+            tags.extend_from_slice(metadata.tags());
+        }
+    }
+    let embedded_loop = tag_loop(&tags).map(|range| EmbeddedLoop { range, source: "LOOPSTART/LOOPLENGTH tags" });
+
+    let track = format.default_track()
+        .ok_or_else(|| ConvertError::nus3audio("No audio track found"))?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate;
+    let mut channels = track.codec_params.channels.map(|channels| channels.count());
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                sample_rate = Some(spec.rate);
+                channels = Some(spec.channels.count());
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+                samples.extend_from_slice(buffer.samples());
+            }
+            // a corrupt frame shouldn't fail the whole song, skip it like players do
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    match (sample_rate, channels) {
+        (Some(sample_rate), Some(channels)) if channels > 0 => Ok(DecodedAudio {
+            sample_rate,
+            channels,
+            samples,
+            embedded_loop,
+        }),
+        _ => Err(ConvertError::nus3audio("Could not tell the sample rate or channel count")),
+    }
+}
+
+struct Resampled {
+    /// Ratio of new to old sample positions, for mapping loop points given in samples
+    conversion_rate: f64,
+    /// Loop found in the input, in the input's samples
+    embedded_loop: Option<EmbeddedLoop>,
+}
+
+/// Resample to a 48 kHz 16-bit wav at `out_path`, keeping mono or stereo unless
+/// `mono` asks for a downmix
+fn resample_wav(path: &Path, out_path: &Path, mono: bool) -> Result<Resampled, ConvertError> {
+    let (spec, audio) = if DECODED_EXTENSIONS.contains(&super::extension(path)) {
+        (None, decode_audio(path)?)
     } else {
-        Ok(())
+        let (spec, audio) = read_wav_samples(path)?;
+        (Some(spec), audio)
+    };
+    let resampled = Resampled {
+        conversion_rate: TARGET_RATE as f64 / audio.sample_rate as f64,
+        embedded_loop: audio.embedded_loop,
+    };
+
+    let channels = audio.channels;
+    if channels > 2 && !mono {
+        return Err(ConvertError::nus3audio(&format!(
+            "The audio has {} channels, only mono and stereo are supported. Add 'mono' to downmix it.", channels
+        )));
+    }
+    let out_channels = if mono { 1 } else { channels };
+
+    let already_converted = path == out_path
+        && audio.sample_rate == TARGET_RATE
+        && channels == out_channels
+        && spec.map_or(false, |spec| spec.sample_format == SampleFormat::Int && spec.bits_per_sample == 16);
+    if already_converted {
+        return Ok(resampled);
     }
 
+    let samples: Vec<f32> = if out_channels == channels {
+        audio.samples
+    } else {
+        audio.samples
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect()
+    };
+
+    let samples = if audio.sample_rate == TARGET_RATE {
+        samples
+    } else {
+        samplerate::convert(audio.sample_rate, TARGET_RATE, out_channels, ConverterType::SincBestQuality, &samples)?
+    };
+
+    let out_spec = WavSpec {
+        channels: out_channels as u16,
+        sample_rate: TARGET_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(out_path, out_spec)?;
+    for sample in samples {
+        writer.write_sample((sample * 32767.0).round().max(-32768.0).min(32767.0) as i16)?;
+    }
+    writer.finalize()?;
+
+    Ok(resampled)
+}
+
+/// Format output samples as a timestamp `message_to_range` maps back to the same sample
+fn samples_to_timestamp(samples: usize) -> String {
+    let micros = samples as u64 * 1_000_000 / TARGET_RATE as u64;
+    format!("{}:{:02}.{:06}", micros / 60_000_000, micros / 1_000_000 % 60, micros % 1_000_000)
+}
+
+/// Look for a seamless loop in a resampled wav, noting what was found so the
+/// user can adjust it
+fn detect_loop(path: &Path, notes: &mut Vec<String>) -> Result<Option<Range<usize>>, ConvertError> {
+    let (_, audio) = read_wav_samples(path)?;
+    let mono: Vec<f32> = audio.samples
+        .chunks(audio.channels)
+        .map(|frame| frame.iter().sum::<f32>() / audio.channels as f32)
+        .collect();
+
+    match autoloop::find_loop(&mono, audio.sample_rate) {
+        Some(found) => {
+            let range = format!("{}-{}", samples_to_timestamp(found.start), samples_to_timestamp(found.end));
+            notes.push(format!(
+                "Detected loop {} (samples {}-{}), {:.0}% similar. Put '{}' in your message to adjust it.",
+                range,
+                found.start,
+                found.end,
+                found.similarity.max(0.0) * 100.0,
+                range
+            ));
+            Ok(Some(found.start..found.end))
+        }
+        None => {
+            notes.push(String::from("No loop detected, the song is too short or too quiet"));
+            Ok(None)
+        }
+    }
+}
+
+/// Apply gain to a resampled wav so its integrated loudness hits `target`, or
+/// as close as the true peak ceiling allows
+fn normalize_wav(path: &Path, target: f64, notes: &mut Vec<String>) -> Result<(), ConvertError> {
+    let (spec, audio) = read_wav_samples(path)?;
+    let before = match loudness::integrated_loudness(&audio.samples, audio.channels) {
+        Some(before) => before,
+        None => {
+            notes.push(String::from("Not normalized, the song is too short or too quiet to measure"));
+            return Ok(());
+        }
+    };
+    let peak = loudness::to_db(loudness::true_peak(&audio.samples, audio.channels));
+
+    let mut gain = target - before;
+    let limited = peak + gain > TRUE_PEAK_CEILING;
+    if limited {
+        gain = TRUE_PEAK_CEILING - peak;
+    }
+
+    let scale = 10f64.powf(gain / 20.0) as f32;
+    let mut writer = WavWriter::create(path, spec)?;
+    for sample in audio.samples {
+        writer.write_sample((sample * scale * 32767.0).round().max(-32768.0).min(32767.0) as i16)?;
+    }
+    writer.finalize()?;
+
+    notes.push(format!(
+        "Normalized from {:.1} to {:.1} LUFS ({:+.1} dB, true peak {:.1} dBTP){}",
+        before,
+        before + gain,
+        gain,
+        peak + gain,
+        if limited {
+            format!(", held back from {:.1} LUFS by the {:.0} dBTP peak limit", target, TRUE_PEAK_CEILING)
+        } else {
+            String::new()
+        }
+    ));
+
+    Ok(())
 }
 
 fn get_wav_sample_count(path: &Path) -> Result<u32, ConvertError> {
     let wav = WavReader::new(fs::File::open(path)?)?;
-    Ok(wav.len())
+    Ok(wav.duration())
 }
 
-impl Converter for Nus3audioConverter {
-    fn get_conversion(&self, file_extension: &str, _: &Path) -> Convert {
-        match file_extension {
-            "wav" | "lopus" => Convert::To,
-            "nus3audio" => Convert::From,
-            _ => Convert::None
+/// Encode anything `resample_wav` reads to lopus, along with whether a loop
+/// was set. `.lopus` files are passed through as they are.
+fn encode_lopus(path: &Path, options: &EncodeOptions, notes: &mut Vec<String>) -> Result<(Vec<u8>, bool), ConvertError> {
+    let mut lopuspath = PathBuf::from(path);
+    lopuspath.set_extension("lopus");
+    if lopuspath == path {
+        return Ok((fs::read(path)?, false));
+    }
+
+    let mut wav_path = PathBuf::from(path);
+    wav_path.set_extension("wav");
+
+    let resampled = resample_wav(path, &wav_path, options.mono)?;
+    if let Some(target) = options.normalize {
+        normalize_wav(&wav_path, target, notes)?;
+    }
+    let new_samples = get_wav_sample_count(&wav_path)? as usize;
+
+    let mut command = 
+        Command::new("dotnet");
+
+    command
+        .arg("vgaudio/netcoreapp2.0/VGAudioCli.dll")
+        .arg("-c")
+        .arg(&wav_path)
+        .arg(&lopuspath)
+        .arg("--bitrate")
+        .arg(options.bitrate.to_string())
+        .arg("--opusheader")
+        .arg("namco");
+    if options.cbr {
+        command.arg("--CBR");
+    }
+
+    let audio_loop = match (&options.loop_range, resampled.embedded_loop) {
+        (Some(loop_range), _) => Some(message_to_range(loop_range, new_samples, resampled.conversion_rate)?),
+        (None, _) if options.autoloop => detect_loop(&wav_path, notes)?,
+        (None, Some(embedded)) => {
+            let start = f64_mul_round(embedded.range.start as f64, resampled.conversion_rate);
+            let end = f64_mul_round(embedded.range.end as f64, resampled.conversion_rate).min(new_samples);
+            // a loop past the end of the audio is empty or backwards once clamped
+            if end > start {
+                notes.push(format!("Looping {}-{} from the {}", start, end, embedded.source));
+                Some(start..end)
+            } else {
+                notes.push(format!(
+                    "Ignored the loop from the {}, it starts at sample {} but the song is only {} long",
+                    embedded.source, start, new_samples
+                ));
+                None
+            }
         }
+        (None, None) => None,
+    };
+
+    let looped = audio_loop.is_some();
+    if let Some(audio_loop) = audio_loop {
+        command
+            .arg("-l")
+            .arg(format!("{}-{}", audio_loop.start, audio_loop.end));
+    }
+
+    let out = command.output()?;
+    if wav_path != path {
+        fs::remove_file(&wav_path)?;
     }
 
-    fn convert_to(&self, path: &Path, message: Option<&str>) -> Result<PathBuf, ConvertError> {
-        let mut lopuspath = PathBuf::from(path);
-        lopuspath.set_extension("lopus");
-        if lopuspath != path {
-            let old_samples = get_wav_sample_count(path)?;
-            resample_wav(path)?;
-            let new_samples = get_wav_sample_count(path)?;
-            let conversion_rate = (new_samples as f64) / (old_samples as f64);
-
-            let mut command = 
-                Command::new("dotnet");
-
-            command
-                .arg("vgaudio/netcoreapp2.0/VGAudioCli.dll")
-                .arg("-c")
-                .arg(path)
-                .arg(&lopuspath)
-                .arg("--bitrate")
-                .arg("64000")
-                .arg("--CBR")
-                .arg("--opusheader")
-                .arg("namco");
-
-            if let Some(message) = message {
-                let audio_loop = message_to_range(message, new_samples as _, conversion_rate)?;
-                command
-                    .arg("-l")
-                    .arg(format!("{}-{}", audio_loop.start, audio_loop.end));
+    let failed = !out.status.success() |
+                 !lopuspath.exists()   |
+                 (fs::metadata(&lopuspath)?.len() == 0);
+
+    if failed {
+        return Err(ConvertError::nus3audio(
+            &(String::from(std::str::from_utf8(&out.stderr[..])?)
+             + std::str::from_utf8(&out.stdout[..])?)
+        ))
+    }
+
+    notes.push(format!(
+        "Encoded at {} kbps {}",
+        options.bitrate / 1000,
+        if options.cbr { "CBR" } else { "VBR" }
+    ));
+
+    let lopus_bytes = fs::read(&lopuspath)?;
+    fs::remove_file(&lopuspath)?;
+    Ok((lopus_bytes, looped))
+}
+
+fn write_nus3audio(outpath: &Path, nus3_file: &Nus3audioFile, notes: &mut Vec<String>) -> Result<(), ConvertError> {
+    let mut file_bytes = Vec::with_capacity(nus3_file.calc_size());
+    nus3_file.write(&mut file_bytes);
+    fs::write(outpath, &file_bytes[..])?;
+    notes.push(format!("{} is {}", outpath.file_name()?.to_str()?, human_size(file_bytes.len() as u64)));
+    Ok(())
+}
+
+/// Most a zip of tracks can unpack to, in total
+const MAX_TRACK_ZIP_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Optional manifest in a zip of tracks, `name,id,file` lines like a playlist's.
+/// Files it doesn't list are named `<id>_<name>.wav` or just `<name>.wav`.
+const TRACK_MANIFEST: &str = "tracks.csv";
+
+/// The file name of a zip entry, `None` for folders and the junk archivers add
+fn zip_entry_file_name(name: &str) -> Option<&str> {
+    if name.ends_with('/') || name.starts_with("__MACOSX/") {
+        return None;
+    }
+    name.rsplit('/').next().filter(|name| !name.starts_with('.'))
+}
+
+/// Whether a zip holds tracks for a nus3audio, rather than files for a sarc
+pub fn is_track_zip(path: &Path) -> bool {
+    let mut zip = match fs::File::open(path).ok().and_then(|file| ZipArchive::new(file).ok()) {
+        Some(zip) => zip,
+        None => return false,
+    };
+
+    let mut has_tracks = false;
+    for i in 0..zip.len() {
+        let entry = match zip.by_index(i) {
+            Ok(entry) => entry,
+            Err(_) => return false,
+        };
+        let name = match zip_entry_file_name(entry.name()) {
+            Some(name) => name,
+            None => continue,
+        };
+        match super::extension(Path::new(name)) {
+            "wav" | "lopus" | "idsp" => has_tracks = true,
+            ext if DECODED_EXTENSIONS.contains(&ext) => has_tracks = true,
+            "nus3audio" | "nus3bank" => {}
+            _ if name == TRACK_MANIFEST => {}
+            _ => return false,
+        }
+    }
+
+    has_tracks
+}
+
+struct TrackSource {
+    file: String,
+    name: String,
+    /// Id to give or replace, otherwise the track replaces the one with the
+    /// same name or takes the next free id
+    id: Option<u32>,
+}
+
+impl TrackSource {
+    /// `<id>_<name>.wav`, as tracks are exported, or `<name>.wav`
+    fn from_file_name(file: &str) -> Self {
+        let stem = Path::new(file).file_stem().and_then(|stem| stem.to_str()).unwrap_or(file);
+        let id_and_name = stem.find('_').and_then(|split| {
+            let id = u32::from_str(&stem[..split]).ok()?;
+            Some((id, &stem[split + 1..])).filter(|(_, name)| !name.is_empty())
+        });
+
+        match id_and_name {
+            Some((id, name)) => TrackSource { file: file.to_owned(), name: name.to_owned(), id: Some(id) },
+            None => TrackSource { file: file.to_owned(), name: stem.to_owned(), id: None },
+        }
+    }
+}
+
+fn parse_track_manifest(manifest: &str) -> Result<Vec<TrackSource>, ConvertError> {
+    manifest
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with("name,"))
+        .map(|line| {
+            let bad_line = || ConvertError::message_format(&format!(
+                "Bad {} line '{}'. Use name,id,file with the id left empty to pick one", TRACK_MANIFEST, line
+            ));
+            let fields: Vec<&str> = line.splitn(3, ',').map(str::trim).collect();
+            match &fields[..] {
+                &[name, id, file] if !name.is_empty() && !file.is_empty() => Ok(TrackSource {
+                    file: file.to_owned(),
+                    name: name.to_owned(),
+                    id: if id.is_empty() { None } else { Some(u32::from_str(id).map_err(|_| bad_line())?) },
+                }),
+                _ => Err(bad_line()),
             }
+        })
+        .collect()
+}
 
-            let out = command.output()?;
+/// Build a nus3audio from a zip of tracks, or patch the nus3audio in the zip
+/// by replacing the tracks that match by id or name. A nus3bank in the zip
+/// gives tracks without an id the id of their tone.
+fn build_from_zip(path: &Path, options: &EncodeOptions, notes: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
+    if options.loop_range.is_some() {
+        return Err(ConvertError::message_format(
+            "Loop points can't be given for a whole zip of tracks. Use wav smpl chunks or LOOPSTART tags \
+             in each file, or 'autoloop'."
+        ));
+    }
 
-            let failed = !out.status.success() |
-                         !lopuspath.exists()   |
-                         (fs::metadata(&lopuspath)?.len() == 0);
+    let mut dir = PathBuf::from(path);
+    dir.set_extension("tracks");
+    fs::create_dir_all(&dir)?;
+    let result = build_from_zip_dir(path, &dir, options, notes);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// Where a track from a zip is unpacked to. Each track gets a folder of its
+/// own, so the wav and lopus written next to it while encoding can't
+/// overwrite another track with the same stem (`foo.mp3` and `foo.wav`).
+fn unpacked_track_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(name).join(name)
+}
 
-            if failed {
-                return Err(ConvertError::nus3audio(
-                    &(String::from(std::str::from_utf8(&out.stderr[..])?)
-                     + std::str::from_utf8(&out.stdout[..])?)
-                ))
+fn build_from_zip_dir(
+    path: &Path,
+    dir: &Path,
+    options: &EncodeOptions,
+    notes: &mut Vec<String>,
+) -> Result<PathBuf, ConvertError> {
+    let mut zip = ZipArchive::new(fs::File::open(path)?)?;
+    let mut base: Option<(String, Nus3audioFile)> = None;
+    let mut bank: Option<(String, Nus3bank)> = None;
+    let mut manifest = None;
+    let mut files: Vec<String> = Vec::new();
+    let mut remaining = MAX_TRACK_ZIP_BYTES;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let name = match zip_entry_file_name(entry.name()) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        // the sizes in a zip's directory can lie, so don't read past the budget either way
+        let too_big = || ConvertError::nus3audio(&format!(
+            "The zip holds more than {} of files, too much to build a nus3audio from", human_size(MAX_TRACK_ZIP_BYTES)
+        ));
+        if entry.size() > remaining {
+            return Err(too_big());
+        }
+        let mut data = Vec::new();
+        Read::by_ref(&mut entry).take(remaining + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > remaining {
+            return Err(too_big());
+        }
+        remaining -= data.len() as u64;
+
+        if name == TRACK_MANIFEST {
+            manifest = Some(parse_track_manifest(std::str::from_utf8(&data)?)?);
+        } else if super::extension(Path::new(&name)) == "nus3audio" {
+            if base.is_some() {
+                return Err(ConvertError::nus3audio("Only one nus3audio can be patched at a time"));
+            }
+            let nus3_file = read_nus3audio(&data)
+                .map_err(|e| ConvertError::nus3audio(&format!("{}: {}", name, e)))?;
+            base = Some((name, nus3_file));
+        } else if super::extension(Path::new(&name)) == "nus3bank" {
+            if bank.is_some() {
+                return Err(ConvertError::nus3bank("Only one nus3bank can be paired at a time"));
+            }
+            bank = Some((name, Nus3bank::from_bytes(&data)?));
+        } else if files.contains(&name) {
+            return Err(ConvertError::nus3audio(&format!("There are two files named {}", name)));
+        } else {
+            fs::create_dir_all(dir.join(&name))?;
+            fs::write(unpacked_track_path(dir, &name), data)?;
+            files.push(name);
+        }
+    }
+
+    let mut sources = manifest.unwrap_or_default();
+    for source in &sources {
+        if !files.contains(&source.file) {
+            return Err(ConvertError::nus3audio(&format!(
+                "{} lists {}, which isn't in the zip", TRACK_MANIFEST, source.file
+            )));
+        }
+    }
+    files.sort();
+    for file in files {
+        if !sources.iter().any(|source| source.file == file) {
+            sources.push(TrackSource::from_file_name(&file));
+        }
+    }
+    if let Some((_, bank)) = &bank {
+        for source in sources.iter_mut().filter(|source| source.id.is_none()) {
+            source.id = bank.tone_id(&source.name);
+        }
+    }
+    // tracks with ids go first so the free ids handed out don't take theirs
+    sources.sort_by_key(|source| source.id.is_none());
+
+    let patching = base.is_some();
+    let (out_name, mut nus3_file) = match base {
+        Some(base) => base,
+        None => (
+            format!("{}.nus3audio", path.file_stem()?.to_str()?),
+            Nus3audioFile { files: Vec::new() },
+        ),
+    };
+
+    let mut used_ids = Vec::new();
+    let (mut replaced, mut added, mut encoded, mut looped) = (0, 0, 0, 0);
+    for source in sources {
+        let track_path = unpacked_track_path(dir, &source.file);
+        let data = match super::extension(&track_path) {
+            "idsp" | "lopus" => fs::read(&track_path)?,
+            _ => {
+                // one note per track would be too long a reply for voice packs,
+                // they're summed up below instead
+                let (data, track_looped) = encode_lopus(&track_path, options, &mut Vec::new())
+                    .map_err(|e| ConvertError::nus3audio(&format!("{}: {}", source.file, e.message)))?;
+                encoded += 1;
+                if track_looped {
+                    looped += 1;
+                }
+                data
+            }
+        };
+
+        // a track found by name takes the source's id, which fixes ids that
+        // have drifted from the bank's
+        let existing = source.id
+            .and_then(|id| nus3_file.files.iter().position(|file| file.id == id))
+            .or_else(|| nus3_file.files.iter().position(|file| file.name == source.name));
+        let id = match source.id.or_else(|| existing.map(|i| nus3_file.files[i].id)) {
+            Some(id) => id,
+            None => nus3_file.files
+                .iter()
+                .map(|file| file.id)
+                .max()
+                .map_or(Some(0), |id| id.checked_add(1))
+                .ok_or_else(|| ConvertError::nus3audio(&format!("There's no free track id left for {}", source.file)))?,
+        };
+        if used_ids.contains(&id) {
+            return Err(ConvertError::nus3audio(&format!(
+                "{} would be track {} ({}), which another file in the zip already is", source.file, source.name, id
+            )));
+        }
+        used_ids.push(id);
+
+        let track = AudioFile { name: source.name, id, data };
+        match existing {
+            Some(i) => {
+                nus3_file.files[i] = track;
+                replaced += 1;
+            }
+            None => {
+                nus3_file.files.push(track);
+                added += 1;
             }
         }
+    }
+    if !patching {
+        nus3_file.files.sort_by_key(|file| file.id);
+    }
+
+    notes.push(if patching {
+        format!("Patched {}: replaced {} track(s), added {}", out_name, replaced, added)
+    } else {
+        format!("Built {} with {} track(s)", out_name, added)
+    });
+    if let Some((bank_name, bank)) = &bank {
+        let mismatched: Vec<String> = nus3_file.files
+            .iter()
+            .filter_map(|file| {
+                let tone_id = bank.tone_id(&file.name)?;
+                if tone_id == file.id {
+                    None
+                } else {
+                    Some(format!("{} (track {}, tone {})", file.name, file.id, tone_id))
+                }
+            })
+            .collect();
+        if !mismatched.is_empty() {
+            notes.push(format!(
+                "These tracks have different ids than their tones in {}: {}{}",
+                bank_name,
+                mismatched.iter().take(10).cloned().collect::<Vec<_>>().join(", "),
+                if mismatched.len() > 10 { ", ..." } else { "" }
+            ));
+        }
+    }
+
+    if encoded > 0 {
+        notes.push(format!(
+            "Encoded {} track(s) at {} kbps {}, {} of them looped",
+            encoded,
+            options.bitrate / 1000,
+            if options.cbr { "CBR" } else { "VBR" },
+            looped
+        ));
+    }
+
+    let outpath = path.with_file_name(&out_name);
+    write_nus3audio(&outpath, &nus3_file, notes)?;
+    Ok(outpath)
+}
+
+impl Converter for Nus3audioConverter {
+    fn get_conversion(&self, file_extension: &str, path: &Path) -> Convert {
+        match file_extension {
+            "wav" | "lopus" => Convert::To,
+            "zip" if is_track_zip(path) => Convert::To,
+            ext if DECODED_EXTENSIONS.contains(&ext) => Convert::To,
+            "nus3audio" => Convert::From,
+            _ => Convert::None
+        }
+    }
+
+    fn convert_to(&self, path: &Path, message: Option<&str>, notes: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
+        let options = EncodeOptions::parse(message)?;
+        if super::extension(path) == "zip" {
+            return build_from_zip(path, &options, notes);
+        }
 
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("nus3audio");
 
-        let lopus_bytes = fs::read(lopuspath)?;
         let nus3_file = Nus3audioFile {
             files: vec![
                 AudioFile {
                     name: String::from(path.file_stem()?.to_str()?),
                     id: 0,
-                    data: lopus_bytes
+                    data: encode_lopus(path, &options, notes)?.0
                 }
             ]
         };
-        let mut file_bytes = Vec::with_capacity(nus3_file.calc_size());
-        nus3_file.write(&mut file_bytes);
-        fs::write(&outpath, &file_bytes[..])?;
+        write_nus3audio(&outpath, &nus3_file, notes)?;
         Ok(PathBuf::from(outpath))
     }
 
-    fn convert_from(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
-        let nus3_file = Nus3audioFile::open(path)?;
-        let mut audiofile_path = PathBuf::from("/tmp/converter/");
-        audiofile_path.push(nus3_file.files[0].filename());
-        let mut outpath = audiofile_path.clone();
-        outpath.set_extension("wav");
-        fs::write(&audiofile_path, &nus3_file.files[0].data[..])?;
-        let out = Command::new("dotnet")
-            .arg("vgaudio/netcoreapp2.0/VGAudioCli.dll")
-            .arg("-c")
-            .arg(&audiofile_path)
-            .arg(&outpath)
-            .output()?;
-        
-        fs::remove_file(&audiofile_path)?;
-        
-        if !out.status.success() || !outpath.exists() {
-            Err(ConvertError::nus3audio(std::str::from_utf8(&out.stdout[..])?))
+    fn convert_from(&self, path: &Path, message: Option<&str>, notes: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
+        let nus3_file = read_nus3audio(&fs::read(path)?).map_err(|e| ConvertError::nus3audio(&e))?;
+        if nus3_file.files.is_empty() {
+            return Err(ConvertError::nus3audio("The nus3audio has no tracks"));
+        }
+
+        let track = message
+            .unwrap_or("")
+            .split_whitespace()
+            .find(|token| token.starts_with("track="))
+            .map(|token| &token["track=".len()..]);
+
+        let file = match track {
+            Some(track) => Some(find_track(&nus3_file, track)?),
+            None if nus3_file.files.len() == 1 => Some(&nus3_file.files[0]),
+            None => None,
+        };
+        if let Some(file) = file {
+            return decode_track(file);
+        }
+
+        let mut outpath = PathBuf::from(path);
+        outpath.set_extension("zip");
+        let failed = write_track_zip(&outpath, &nus3_file, true)?;
+
+        // wavs are ~10x the size of the opus they came from, so big voice
+        // packs get their tracks as they are in the nus3audio instead
+        let wav_size = fs::metadata(&outpath)?.len();
+        if wav_size > UPLOAD_LIMIT {
+            write_track_zip(&outpath, &nus3_file, false)?;
+            let raw_size = fs::metadata(&outpath)?.len();
+            if raw_size > UPLOAD_LIMIT {
+                fs::remove_file(&outpath)?;
+                return Err(ConvertError::nus3audio(&format!(
+                    "The {} tracks are {} even without decoding, over the {} upload limit. \
+                     Add 'track=<name or id>' to get them one at a time.",
+                    nus3_file.files.len(),
+                    human_size(raw_size),
+                    human_size(UPLOAD_LIMIT)
+                )));
+            }
+
+            notes.push(format!(
+                "Exported {} tracks as they are in the nus3audio (lopus/idsp), as wavs they came to {}, over the {} \
+                 upload limit. Add 'track=<name or id>' to get one as a wav",
+                nus3_file.files.len(),
+                human_size(wav_size),
+                human_size(UPLOAD_LIMIT)
+            ));
+            return Ok(outpath);
+        }
+
+        notes.push(format!(
+            "Exported {} tracks as <id>_<name>.wav, add 'track=<name or id>' to get just one",
+            nus3_file.files.len()
+        ));
+        if !failed.is_empty() {
+            notes.push(format!(
+                "Couldn't decode {} track(s), included as they were: {}{}",
+                failed.len(),
+                failed.iter().take(10).cloned().collect::<Vec<_>>().join(", "),
+                if failed.len() > 10 { ", ..." } else { "" }
+            ));
+        }
+
+        Ok(outpath)
+    }
+}
+
+/// Write every track to a zip as `<id>_<name>.wav`, or as the lopus/idsp data
+/// they're stored as when `decode` is false. Returns the tracks that couldn't
+/// be decoded, which are stored as they are.
+fn write_track_zip(outpath: &Path, nus3_file: &Nus3audioFile, decode: bool) -> Result<Vec<String>, ConvertError> {
+    let mut zip = ZipWriter::new(fs::File::create(outpath)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
 
+    let mut failed = Vec::new();
+    for file in &nus3_file.files {
+        let name = track_file_stem(file);
+        let wav_path = if decode { decode_track(file).ok() } else { None };
+        match wav_path {
+            Some(wav_path) => {
+                let wav = fs::read(&wav_path);
+                fs::remove_file(&wav_path)?;
+                zip.start_file(format!("{}.wav", name), options)?;
+                zip.write_all(&wav?)?;
+            }
+            // dummy and odd entries can't be decoded, keep them as they are
+            None => {
+                zip.start_file(format!("{}.{}", name, track_extension(file)), options)?;
+                zip.write_all(&file.data)?;
+                if decode {
+                    failed.push(name);
+                }
+            }
         }
-        else {
-            Ok(PathBuf::from(outpath))
-        } 
     }
+    zip.finish()?;
+
+    Ok(failed)
+}
+
+/// Pick a track by its name, or failing that its id
+fn find_track<'a>(nus3_file: &'a Nus3audioFile, track: &str) -> Result<&'a AudioFile, ConvertError> {
+    nus3_file.files
+        .iter()
+        .find(|file| file.name == track)
+        .or_else(|| {
+            let id = u32::from_str(track).ok()?;
+            nus3_file.files.iter().find(|file| file.id == id)
+        })
+        .ok_or_else(|| {
+            let tracks = nus3_file.files
+                .iter()
+                .take(30)
+                .map(|file| format!("{} ({})", file.name, file.id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            ConvertError::nus3audio(&format!(
+                "No track named '{}'. Tracks: {}{}",
+                track,
+                tracks,
+                if nus3_file.files.len() > 30 { ", ..." } else { "" }
+            ))
+        })
+}
+
+/// A track's name with anything that can't go in a file name replaced
+fn safe_track_name(file: &AudioFile) -> String {
+    file.name
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect()
+}
+
+/// Extension of a track's encoding, `lopus` or `idsp`
+fn track_extension(file: &AudioFile) -> String {
+    file.filename().rsplit('.').next().unwrap_or("bin").to_owned()
+}
+
+fn track_file_stem(file: &AudioFile) -> String {
+    format!("{}_{}", file.id, safe_track_name(file))
 }
 
+/// Decode a single track to a wav with VGAudio
+fn decode_track(file: &AudioFile) -> Result<PathBuf, ConvertError> {
+    let mut audiofile_path = PathBuf::from("/tmp/converter/");
+    audiofile_path.push(format!("{}.{}", safe_track_name(file), track_extension(file)));
+    let mut outpath = audiofile_path.clone();
+    outpath.set_extension("wav");
+    fs::write(&audiofile_path, &file.data[..])?;
+    let out = Command::new("dotnet")
+        .arg("vgaudio/netcoreapp2.0/VGAudioCli.dll")
+        .arg("-c")
+        .arg(&audiofile_path)
+        .arg(&outpath)
+        .output()?;
+
+    fs::remove_file(&audiofile_path)?;
+
+    if !out.status.success() || !outpath.exists() {
+        Err(ConvertError::nus3audio(std::str::from_utf8(&out.stdout[..])?))
+    }
+    else {
+        Ok(outpath)
+    }
+}
@@ -0,0 +1,376 @@
+use super::error::ConvertError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::{Converter, Convert};
+use byteorder::{ByteOrder, LittleEndian};
+use serde::{Deserialize, Serialize};
+
+pub struct Nus3bankConverter;
+
+/// Tones listed in the reply, the rest are only in the yml
+const LISTED_TONES: usize = 20;
+
+/// What `convert` passes as the extension of a yml `is_bank_yaml` accepts,
+/// which no real extension can be
+pub const BANK_YAML_EXTENSION: &str = "nus3bank.yml";
+
+/// A nus3bank split into its sections. The TONE section is parsed into tones
+/// and the rest are kept as they are.
+pub struct Nus3bank {
+    /// Every section in file order, TONE's data is rebuilt from `tones`
+    pub sections: Vec<([u8; 4], Vec<u8>)>,
+    pub tones: Vec<Tone>,
+}
+
+/// A tone, whose id is its index in the bank. Paired nus3audio tracks share
+/// the id and name of their tone.
+pub struct Tone {
+    pub name: Option<String>,
+    /// Bytes before the name's length byte, or the whole tone if it has no name
+    pub prefix: Vec<u8>,
+    /// Bytes after the name and its padding
+    pub suffix: Vec<u8>,
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn bank_error(message: &str) -> ConvertError {
+    ConvertError::nus3bank(message)
+}
+
+impl Tone {
+    /// The name is a length byte (counting the null) then a null terminated
+    /// string padded to 4 bytes. It follows 8 bytes of header, or 12 when bit
+    /// 0x80 of the flags at 0x7 is set, as vgmstream reads it. Tones of 0xC
+    /// bytes or less aren't sounds and have no name.
+    fn parse(data: &[u8]) -> Tone {
+        let unnamed = || Tone { name: None, prefix: data.to_vec(), suffix: Vec::new() };
+        if data.len() <= 0xC {
+            return unnamed();
+        }
+
+        let offset = if data[0x7] & 0x80 != 0 { 0xC } else { 0x8 };
+        let len = data[offset] as usize;
+        let end = offset + 1 + len;
+        let fits = len >= 2
+            && end <= data.len()
+            && data[offset + 1..end - 1].iter().all(|c| c.is_ascii_graphic())
+            && data[end - 1] == 0
+            && data[end..align4(end).min(data.len())].iter().all(|&c| c == 0);
+        if !fits {
+            return unnamed();
+        }
+
+        Tone {
+            name: Some(String::from_utf8_lossy(&data[offset + 1..end - 1]).into_owned()),
+            prefix: data[..offset].to_vec(),
+            suffix: data[align4(end).min(data.len())..].to_vec(),
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) -> Result<(), ConvertError> {
+        out.extend_from_slice(&self.prefix);
+        if let Some(name) = &self.name {
+            if name.len() > 254 || !name.bytes().all(|c| c.is_ascii_graphic()) {
+                return Err(bank_error(&format!("Bad tone name '{}', use up to 254 ascii characters without spaces", name)));
+            }
+            let start = out.len() - self.prefix.len();
+            out.push(name.len() as u8 + 1);
+            out.extend_from_slice(name.as_bytes());
+            out.push(0);
+            out.resize(start + align4(out.len() - start), 0);
+        }
+        out.extend_from_slice(&self.suffix);
+        Ok(())
+    }
+}
+
+impl Nus3bank {
+    pub fn from_bytes(data: &[u8]) -> Result<Nus3bank, ConvertError> {
+        if data.len() < 0x18 || &data[..4] != b"NUS3" || &data[8..12] != b"BANK" || &data[12..16] != b"TOC " {
+            return Err(bank_error("Not a nus3bank"));
+        }
+        let read_u32 = |offset: usize| -> Result<usize, ConvertError> {
+            data.get(offset..offset + 4)
+                .map(|bytes| LittleEndian::read_u32(bytes) as usize)
+                .ok_or_else(|| bank_error("The nus3bank ends early"))
+        };
+
+        let count = read_u32(0x14)?;
+        if count.checked_mul(8).and_then(|size| size.checked_add(0x18)).map_or(true, |end| end > data.len()) {
+            return Err(bank_error("The nus3bank's TOC is bigger than the file"));
+        }
+        let mut offset = 0x14 + read_u32(0x10)?;
+        let mut sections = Vec::new();
+        for i in 0..count {
+            let toc_entry = 0x18 + i * 8;
+            let size = read_u32(toc_entry + 4)?;
+            let section_data = data.get(offset + 8..offset + 8 + size)
+                .ok_or_else(|| bank_error("The nus3bank ends early"))?;
+            if data[offset..offset + 4] != data[toc_entry..toc_entry + 4] {
+                return Err(bank_error("The nus3bank's sections don't match its TOC"));
+            }
+            let mut magic = [0; 4];
+            magic.copy_from_slice(&data[offset..offset + 4]);
+            sections.push((magic, section_data.to_vec()));
+            offset += 8 + size;
+        }
+
+        let tones = match sections.iter().find(|(magic, _)| magic == b"TONE") {
+            Some((_, tone_data)) => Self::parse_tones(tone_data)?,
+            None => Vec::new(),
+        };
+
+        Ok(Nus3bank { sections, tones })
+    }
+
+    fn parse_tones(data: &[u8]) -> Result<Vec<Tone>, ConvertError> {
+        let read_u32 = |offset: usize| -> Result<usize, ConvertError> {
+            data.get(offset..offset + 4)
+                .map(|bytes| LittleEndian::read_u32(bytes) as usize)
+                .ok_or_else(|| bank_error("The TONE section ends early"))
+        };
+
+        let count = read_u32(0)?;
+        if count.checked_mul(8).and_then(|size| size.checked_add(4)).map_or(true, |end| end > data.len()) {
+            return Err(bank_error("The TONE section's table is bigger than the section"));
+        }
+
+        (0..count)
+            .map(|i| {
+                let (offset, size) = (read_u32(4 + i * 8)?, read_u32(8 + i * 8)?);
+                let tone_data = data.get(offset..offset + size)
+                    .ok_or_else(|| bank_error(&format!("Tone {} is outside the TONE section", i)))?;
+                Ok(Tone::parse(tone_data))
+            })
+            .collect()
+    }
+
+    fn tone_section(&self) -> Result<Vec<u8>, ConvertError> {
+        let table_size = 4 + self.tones.len() * 8;
+        let mut data = vec![0; table_size];
+        LittleEndian::write_u32(&mut data[0..4], self.tones.len() as u32);
+        for (i, tone) in self.tones.iter().enumerate() {
+            let offset = data.len();
+            tone.write(&mut data)?;
+            let size = data.len() - offset;
+            LittleEndian::write_u32(&mut data[4 + i * 8..8 + i * 8], offset as u32);
+            LittleEndian::write_u32(&mut data[8 + i * 8..12 + i * 8], size as u32);
+            data.resize(align4(data.len()), 0);
+        }
+        Ok(data)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ConvertError> {
+        let tone_section = self.tone_section()?;
+        let sections: Vec<(&[u8; 4], &[u8])> = self.sections
+            .iter()
+            .map(|(magic, data)| (magic, if magic == b"TONE" { &tone_section[..] } else { &data[..] }))
+            .collect();
+
+        let mut toc = vec![0; 8 + 4 + sections.len() * 8];
+        toc[..4].copy_from_slice(b"TOC ");
+        LittleEndian::write_u32(&mut toc[4..8], (4 + sections.len() * 8) as u32);
+        LittleEndian::write_u32(&mut toc[8..12], sections.len() as u32);
+        for (i, (magic, data)) in sections.iter().enumerate() {
+            toc[12 + i * 8..16 + i * 8].copy_from_slice(&magic[..]);
+            LittleEndian::write_u32(&mut toc[16 + i * 8..20 + i * 8], data.len() as u32);
+        }
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"NUS3");
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(b"BANK");
+        out.extend_from_slice(&toc);
+        for (magic, data) in sections {
+            out.extend_from_slice(&magic[..]);
+            let mut size = [0; 4];
+            LittleEndian::write_u32(&mut size, data.len() as u32);
+            out.extend_from_slice(&size);
+            out.extend_from_slice(data);
+        }
+        let size = out.len() as u32 - 8;
+        LittleEndian::write_u32(&mut out[4..8], size);
+
+        Ok(out)
+    }
+
+    /// Id of the tone with this name, for keeping a paired nus3audio's tracks in line
+    pub fn tone_id(&self, name: &str) -> Option<u32> {
+        self.tones
+            .iter()
+            .position(|tone| tone.name.as_deref() == Some(name))
+            .map(|id| id as u32)
+    }
+}
+
+/// The yml a nus3bank converts to. Unknown bytes are hex so they survive
+/// editing the names and ids around them.
+#[derive(Serialize, Deserialize)]
+struct BankYaml {
+    sections: Vec<SectionYaml>,
+    tones: Vec<ToneYaml>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SectionYaml {
+    magic: String,
+    /// Empty for TONE, which is rebuilt from `tones`
+    #[serde(default)]
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ToneYaml {
+    id: u32,
+    name: Option<String>,
+    prefix: String,
+    #[serde(default)]
+    suffix: String,
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, ConvertError> {
+    let digits: Vec<u8> = hex.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(bank_error(&format!("Odd number of hex digits in '{}'", hex)));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            u8::from_str_radix(std::str::from_utf8(pair)?, 16)
+                .map_err(|_| bank_error(&format!("Bad hex '{}'", String::from_utf8_lossy(pair))))
+        })
+        .collect()
+}
+
+impl From<&Nus3bank> for BankYaml {
+    fn from(bank: &Nus3bank) -> Self {
+        BankYaml {
+            sections: bank.sections
+                .iter()
+                .map(|(magic, data)| SectionYaml {
+                    magic: String::from_utf8_lossy(magic).into_owned(),
+                    data: if magic == b"TONE" { String::new() } else { to_hex(data) },
+                })
+                .collect(),
+            tones: bank.tones
+                .iter()
+                .enumerate()
+                .map(|(id, tone)| ToneYaml {
+                    id: id as u32,
+                    name: tone.name.clone(),
+                    prefix: to_hex(&tone.prefix),
+                    suffix: to_hex(&tone.suffix),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl BankYaml {
+    /// Ids have to run from 0 with no gaps, since they're the tones' positions
+    /// and a paired nus3audio refers to them
+    fn into_bank(mut self) -> Result<Nus3bank, ConvertError> {
+        self.tones.sort_by_key(|tone| tone.id);
+        if let Some((index, tone)) = self.tones.iter().enumerate().find(|(index, tone)| tone.id as usize != *index) {
+            return Err(bank_error(&format!(
+                "Tone ids have to go 0, 1, 2... without gaps or repeats, expected {} but found {}{}",
+                index,
+                tone.id,
+                tone.name.as_ref().map(|name| format!(" ({})", name)).unwrap_or_default()
+            )));
+        }
+
+        let sections = self.sections
+            .into_iter()
+            .map(|section| {
+                let bytes = section.magic.as_bytes();
+                if bytes.len() != 4 {
+                    return Err(bank_error(&format!("Bad section name '{}', they're 4 characters", section.magic)));
+                }
+                let mut magic = [0; 4];
+                magic.copy_from_slice(bytes);
+                Ok((magic, from_hex(&section.data)?))
+            })
+            .collect::<Result<Vec<_>, ConvertError>>()?;
+
+        let tones = self.tones
+            .into_iter()
+            .map(|tone| Ok(Tone {
+                name: tone.name,
+                prefix: from_hex(&tone.prefix)?,
+                suffix: from_hex(&tone.suffix)?,
+            }))
+            .collect::<Result<Vec<_>, ConvertError>>()?;
+
+        Ok(Nus3bank { sections, tones })
+    }
+}
+
+/// Whether a yml is an exported nus3bank, so the other yml and yaml converters
+/// can leave it alone
+pub fn is_bank_yaml(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|text| {
+            let keys: Vec<&str> = text.lines()
+                .filter(|line| !line.starts_with(' '))
+                .map(str::trim_end)
+                .collect();
+            keys.contains(&"sections:") && keys.contains(&"tones:")
+        })
+        .unwrap_or(false)
+}
+
+impl Converter for Nus3bankConverter {
+    fn get_conversion(&self, file_extension: &str, _: &Path) -> Convert {
+        match file_extension {
+            BANK_YAML_EXTENSION => Convert::To,
+            "nus3bank" => Convert::From,
+            _ => Convert::None,
+        }
+    }
+
+    fn convert_from(&self, path: &Path, _: Option<&str>, notes: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
+        let data = fs::read(path)?;
+        let bank = Nus3bank::from_bytes(&data)?;
+        // the yml is only useful if it builds the same bank back
+        if bank.to_bytes()? != data {
+            return Err(bank_error(
+                "This nus3bank's layout isn't understood well enough to rebuild it byte for byte, \
+                 so it can't be exported to yml"
+            ));
+        }
+        let mut outpath = PathBuf::from(path);
+        outpath.set_extension("yml");
+        fs::write(&outpath, serde_yaml::to_string(&BankYaml::from(&bank))?)?;
+
+        let tones: Vec<String> = bank.tones
+            .iter()
+            .enumerate()
+            .take(LISTED_TONES)
+            .map(|(id, tone)| format!("{} {}", id, tone.name.as_ref().map_or("(unnamed)", String::as_str)))
+            .collect();
+        notes.push(format!(
+            "{} tones:\n{}{}",
+            bank.tones.len(),
+            tones.join("\n"),
+            if bank.tones.len() > LISTED_TONES { "\n...the rest are in the yml" } else { "" }
+        ));
+
+        Ok(outpath)
+    }
+
+    fn convert_to(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
+        let yaml: BankYaml = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+        let mut outpath = PathBuf::from(path);
+        outpath.set_extension("nus3bank");
+        fs::write(&outpath, yaml.into_bank()?.to_bytes()?)?;
+        Ok(outpath)
+    }
+}
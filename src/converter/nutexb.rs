@@ -16,7 +16,7 @@ impl Converter for NutexbConverter {
         }
     }
 
-    fn convert_to(&self, path: &Path, message: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_to(&self, path: &Path, message: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let dds = Dds::read(&mut fs::File::open(path)?).unwrap();
         let mut out_path = PathBuf::from(path);
         out_path.set_extension("nutexb");
@@ -24,7 +24,7 @@ impl Converter for NutexbConverter {
         Ok(out_path)
     }
 
-    fn convert_from(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_from(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         todo!()
     }
 }
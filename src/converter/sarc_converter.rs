@@ -26,13 +26,13 @@ const UNCOMPRESSED_EXTS: &[&str] = &[
 impl super::Converter for SarcConverter {
     fn get_conversion(&self, file_extension: &str, path: &Path) -> Convert {
         match file_extension {
-            "zip" => Convert::To,
+            "zip" if !nus3audio_convert::is_track_zip(path) => Convert::To,
             _ if check_if_sarc(path).unwrap_or(false) => Convert::From,
             _ => Convert::None,
         }
     }
 
-    fn convert_from(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_from(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let sarc = SarcFile::read_from_file(path)?;
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("zip");
@@ -47,7 +47,7 @@ impl super::Converter for SarcConverter {
         Ok(outpath)
     }
 
-    fn convert_to(&self, path: &Path, message: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_to(&self, path: &Path, message: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let mut zip = ZipArchive::new(File::open(path)?)?;
 
         let byte_order = match message {
@@ -13,14 +13,14 @@ impl Converter for SqbConverter {
         }
     }
 
-    fn convert_from(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_from(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("yaml");
         std::fs::write(&outpath, serde_yaml::to_string(&sqb::open(path)?)?)?;
         Ok(outpath)
     }
 
-    fn convert_to(&self, path: &Path, _: Option<&str>) -> Result<PathBuf, ConvertError> {
+    fn convert_to(&self, path: &Path, _: Option<&str>, _: &mut Vec<String>) -> Result<PathBuf, ConvertError> {
         let mut outpath = PathBuf::from(path);
         outpath.set_extension("sqb");
         sqb::save(&outpath, &serde_yaml::from_str(&std::fs::read_to_string(path)?)?)?;
@@ -0,0 +1,733 @@
+use serenity::utils::MessageBuilder;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use lazy_static::lazy_static;
+use hash40::to_hash40;
+use super::MessageHelper;
+
+/// Where a label came from. Sources earlier in the list take precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelSource {
+    Community,
+    Param,
+    Arc,
+    Motion,
+    Sqb,
+}
+
+impl LabelSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            LabelSource::Community => "community",
+            LabelSource::Param => "param",
+            LabelSource::Arc => "arc",
+            LabelSource::Motion => "motion",
+            LabelSource::Sqb => "sqb",
+        }
+    }
+}
+
+enum LabelFormat {
+    /// `0x0123456789,label`
+    Csv,
+    /// `0123456789\tlabel`
+    Tsv,
+    /// one label per line, hashed on load
+    Strings,
+}
+
+/// Label files loaded on startup and on every `%update`, in precedence order
+const LABEL_FILES: &[(LabelSource, &str, LabelFormat)] = &[
+    (LabelSource::Param, PARAM_LABELS_PATH, LabelFormat::Csv),
+    (LabelSource::Motion, "motion_list_labels.txt", LabelFormat::Strings),
+    (LabelSource::Sqb, "sqb_labels.txt", LabelFormat::Strings),
+];
+
+/// Arc path labels built into the bot, nothing installs them at runtime
+const ARC_LABELS: &str = include_str!("hash40s.tsv");
+/// Optional newer arc labels, loaded over the built in ones when present
+pub const ARC_LABELS_PATH: &str = "hash40s.tsv";
+
+struct LabelEntry {
+    hash: u64,
+    source: LabelSource,
+    start: u32,
+    len: u32,
+}
+
+/// Every label from every file, stored back to back in one string with a
+/// sorted index over it, instead of a map entry per label.
+#[derive(Default)]
+pub struct LabelStore {
+    strings: String,
+    /// sorted by hash, then source precedence
+    entries: Vec<LabelEntry>,
+    /// indices into `entries`, sorted by crc, for 32-bit lookups
+    crc_index: Vec<u32>,
+    counts: Vec<(LabelSource, &'static str, usize)>,
+    /// label files that couldn't be loaded, and why
+    missing: Vec<String>,
+}
+
+impl LabelStore {
+    fn label(&self, entry: &LabelEntry) -> &str {
+        &self.strings[entry.start as usize..(entry.start + entry.len) as usize]
+    }
+
+    fn push(&mut self, hash: u64, source: LabelSource, label: &str) {
+        self.entries.push(LabelEntry {
+            hash,
+            source,
+            start: self.strings.len() as u32,
+            len: label.len() as u32,
+        });
+        self.strings.push_str(label);
+    }
+
+    fn load(&mut self, source: LabelSource, path: &'static str, format: &LabelFormat) -> std::io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        self.load_str(source, path, &contents, format);
+
+        Ok(())
+    }
+
+    fn load_str(&mut self, source: LabelSource, path: &'static str, contents: &str, format: &LabelFormat) {
+        let before = self.entries.len();
+        for line in contents.lines() {
+            let line = line.trim_end_matches('\r');
+            let parsed = match format {
+                LabelFormat::Csv => split_label_line(line, ','),
+                LabelFormat::Tsv => split_label_line(line, '\t'),
+                LabelFormat::Strings if !line.is_empty() => Some((hash40_value(line), line)),
+                LabelFormat::Strings => None,
+            };
+            if let Some((hash, label)) = parsed {
+                self.push(hash, source, label);
+            }
+        }
+        self.counts.push((source, path, self.entries.len() - before));
+    }
+
+    fn build_index(&mut self) {
+        self.entries.sort_by(|a, b| a.hash.cmp(&b.hash).then(a.source.cmp(&b.source)));
+        self.entries.dedup_by(|a, b| a.hash == b.hash && a.source == b.source);
+
+        let entries = &self.entries;
+        let mut crc_index: Vec<u32> = (0..entries.len() as u32).collect();
+        crc_index.sort_by_key(|&i| (entries[i as usize].hash & 0xFFFF_FFFF, entries[i as usize].source));
+        self.crc_index = crc_index;
+    }
+
+    fn lookup(&self, hash: u64) -> Vec<(LabelSource, String)> {
+        let found: Vec<&LabelEntry> = if hash >> 32 == 0 {
+            // no length given, so match on the crc alone
+            let start = lower_bound(&self.crc_index, |&i| self.entries[i as usize].hash & 0xFFFF_FFFF, hash);
+            self.crc_index[start..]
+                .iter()
+                .map(|&i| &self.entries[i as usize])
+                .take_while(|entry| entry.hash & 0xFFFF_FFFF == hash)
+                .collect()
+        } else {
+            let start = lower_bound(&self.entries, |entry| entry.hash, hash);
+            self.entries[start..]
+                .iter()
+                .take_while(|entry| entry.hash == hash)
+                .collect()
+        };
+
+        found.into_iter()
+            .map(|entry| (entry.source, self.label(entry).to_owned()))
+            .collect()
+    }
+
+    fn labels_from(&self, source: LabelSource) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.source == source)
+            .map(move |entry| self.label(entry))
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        self.strings.len()
+            + self.entries.len() * std::mem::size_of::<LabelEntry>()
+            + self.crc_index.len() * std::mem::size_of::<u32>()
+    }
+}
+
+/// Index of the first item whose key is not less than `key`
+fn lower_bound<T, F: Fn(&T) -> u64>(items: &[T], item_key: F, key: u64) -> usize {
+    items.binary_search_by(|item| item_key(item).cmp(&key).then(std::cmp::Ordering::Greater))
+        .unwrap_or_else(|i| i)
+}
+
+fn split_label_line(line: &str, separator: char) -> Option<(u64, &str)> {
+    let mut split = line.splitn(2, separator);
+    let hash = split.next()?.trim_start_matches("0x");
+    let label = split.next()?;
+    Some((u64::from_str_radix(hash, 16).ok()?, label))
+}
+
+lazy_static! {
+    static ref STORE: RwLock<LabelStore> = RwLock::new(LabelStore::default());
+}
+
+static LOOKUPS: AtomicU64 = AtomicU64::new(0);
+static LOOKUP_HITS: AtomicU64 = AtomicU64::new(0);
+
+/// Reload every label file from disk, replacing the labels in use. Files that
+/// fail to load are skipped so the rest can still be used, and are reported in
+/// the error. `%update` fetches any that are missing.
+pub fn reload() -> Result<(), String> {
+    // merged into ParamLabels.csv first, which `%update` may have just replaced
+    load_overlay();
+
+    let mut store = LabelStore::default();
+    for (source, path, format) in LABEL_FILES {
+        if let Err(e) = store.load(*source, path, format) {
+            store.missing.push(format!("{} ({}): {}", path, source.name(), e));
+        }
+    }
+
+    // loaded before the built in labels so its labels win when both have a hash
+    match store.load(LabelSource::Arc, ARC_LABELS_PATH, &LabelFormat::Tsv) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            store.missing.push(format!("{} ({}): {}", ARC_LABELS_PATH, LabelSource::Arc.name(), e));
+        }
+        _ => {}
+    }
+    store.load_str(LabelSource::Arc, "hash40s.tsv (built in)", ARC_LABELS, &LabelFormat::Tsv);
+    store.build_index();
+
+    let missing = store.missing.join("\n");
+    *STORE.write().unwrap() = store;
+    set_hash40_labels();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(missing)
+    }
+}
+
+/// Label files that failed to load on the last reload
+pub fn missing_sources() -> Vec<String> {
+    STORE.read().unwrap().missing.clone()
+}
+
+/// motion_list and sqb (de)serialization label hashes through hash40's own table
+fn set_hash40_labels() {
+    let store = STORE.read().unwrap();
+    let overlay = OVERLAY.read().unwrap();
+    hash40::set_labels(
+        store.labels_from(LabelSource::Motion)
+            .chain(store.labels_from(LabelSource::Sqb))
+            .chain(overlay.values().map(|label| &label.label[..]))
+            .map(String::from)
+            .collect::<Vec<_>>()
+    );
+}
+
+/// Every known label for a hash, highest precedence first
+pub fn lookup(hash: u64) -> Vec<(LabelSource, String)> {
+    let labels = lookup_uncounted(hash);
+
+    LOOKUPS.fetch_add(1, Ordering::Relaxed);
+    if !labels.is_empty() {
+        LOOKUP_HITS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    labels
+}
+
+/// `lookup` without counting towards the hit rate in `stats`, for scans that
+/// try every value in a file and would drown out the real lookups
+pub fn lookup_uncounted(hash: u64) -> Vec<(LabelSource, String)> {
+    let mut labels: Vec<(LabelSource, String)> = community_label(hash)
+        .map(|label| (LabelSource::Community, label))
+        .into_iter()
+        .chain(STORE.read().unwrap().lookup(hash))
+        .collect();
+    // community labels are also merged into ParamLabels.csv, only show them once
+    labels.dedup_by(|a, b| a.1 == b.1);
+
+    labels
+}
+
+/// Call `f` with every arc path label
+pub fn for_each_arc_path<F: FnMut(&str)>(f: F) {
+    STORE.read().unwrap().labels_from(LabelSource::Arc).for_each(f);
+}
+
+/// Every distinct word making up a known label, used as the default crack wordlist
+fn label_words() -> Vec<String> {
+    let store = STORE.read().unwrap();
+    let words: BTreeSet<&str> = store.labels_from(LabelSource::Param)
+        .chain(store.labels_from(LabelSource::Arc))
+        .flat_map(|label| label.split(&['_', '/', '.'][..]))
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.into_iter().map(String::from).collect()
+}
+
+pub fn stats() -> String {
+    let store = STORE.read().unwrap();
+    let lookups = LOOKUPS.load(Ordering::Relaxed);
+    let hits = LOOKUP_HITS.load(Ordering::Relaxed);
+    let mut lines: Vec<String> = store.counts
+        .iter()
+        .map(|(source, path, count)| format!("{:<9} {:>8} labels  {}", source.name(), count, path))
+        .collect();
+    lines.insert(0, format!("{:<9} {:>8} labels  {}", LabelSource::Community.name(), OVERLAY.read().unwrap().len(), LABEL_OVERLAY_PATH));
+    lines.push(format!(
+        "\n{} labels indexed in {:.1} MiB",
+        store.entries.len(),
+        store.size_in_bytes() as f64 / (1024.0 * 1024.0)
+    ));
+    lines.push(format!("{} lookups since start, {} found", lookups, hits));
+    if !store.missing.is_empty() {
+        lines.push(format!("\nNot loaded (run %update):\n{}", store.missing.join("\n")));
+    }
+    lines.join("\n")
+}
+
+/// Labels submitted through `%label add`, kept separate from the upstream label files
+const LABEL_OVERLAY_PATH: &str = "label_overlay.tsv";
+/// The labels file ParamXML reads on every conversion
+const PARAM_LABELS_PATH: &str = "paramxml/netcoreapp2.1/ParamLabels.csv";
+
+const LABEL_USAGE: &str = "Use format 'label add [hash] [string]', 'label export' or 'label stats'";
+
+pub struct CommunityLabel {
+    pub label: String,
+    pub submitter: String,
+    pub timestamp: u64,
+}
+
+lazy_static! {
+    static ref OVERLAY: RwLock<BTreeMap<u64, CommunityLabel>> = RwLock::new(BTreeMap::new());
+}
+
+pub fn hash40_value(s: &str) -> u64 {
+    let hash = to_hash40(s);
+    ((hash.strlen() as u64) << 0x20) + hash.crc() as u64
+}
+
+fn community_label(hash: u64) -> Option<String> {
+    let overlay = OVERLAY.read().unwrap();
+    overlay.get(&hash)
+        .or_else(|| {
+            // 32-bit lookups only match on the crc, same as the other tables
+            if hash >> 32 == 0 {
+                overlay.iter()
+                    .find(|(full_hash, _)| *full_hash & 0xFFFF_FFFF == hash)
+                    .map(|(_, label)| label)
+            } else {
+                None
+            }
+        })
+        .map(|label| label.label.clone())
+}
+
+fn parse_overlay_line(line: &str) -> Option<(u64, CommunityLabel)> {
+    let split: Vec<&str> = line.split('\t').collect();
+    if let [hash, label, submitter, timestamp] = split[..] {
+        Some((
+            u64::from_str_radix(hash.trim_start_matches("0x"), 16).ok()?,
+            CommunityLabel {
+                label: label.to_owned(),
+                submitter: submitter.to_owned(),
+                timestamp: timestamp.trim().parse().ok()?,
+            }
+        ))
+    } else {
+        None
+    }
+}
+
+/// Load the overlay from disk and make its labels visible to the converters
+fn load_overlay() {
+    let overlay: BTreeMap<u64, CommunityLabel> = fs::read_to_string(LABEL_OVERLAY_PATH)
+        .map(|contents| contents.lines().filter_map(parse_overlay_line).collect())
+        .unwrap_or_default();
+
+    *OVERLAY.write().unwrap() = overlay;
+    apply_overlay();
+}
+
+/// Merge the overlay into ParamXML's labels file. This needs to be rerun
+/// after `%update` replaces ParamLabels.csv.
+fn apply_overlay() {
+    let overlay = OVERLAY.read().unwrap();
+
+    let param_labels = match fs::read_to_string(PARAM_LABELS_PATH) {
+        Ok(param_labels) => param_labels,
+        Err(_) => return,
+    };
+    let mut lines: Vec<String> = param_labels
+        .lines()
+        .filter(|line| {
+            line.split(',')
+                .next()
+                .and_then(|hash| u64::from_str_radix(hash.trim_start_matches("0x"), 16).ok())
+                .map(|hash| !overlay.contains_key(&hash))
+                .unwrap_or(true)
+        })
+        .map(String::from)
+        .collect();
+    lines.extend(overlay.iter().map(|(hash, label)| to_csv_line(*hash, &label.label)));
+
+    if let Err(e) = fs::write(PARAM_LABELS_PATH, lines.join("\n") + "\n") {
+        println!("Failed to apply label overlay to {}: {:?}", PARAM_LABELS_PATH, e);
+    }
+}
+
+fn to_csv_line(hash: u64, label: &str) -> String {
+    format!("0x{:010x},{}", hash, label)
+}
+
+fn save_overlay(overlay: &BTreeMap<u64, CommunityLabel>) -> std::io::Result<()> {
+    fs::write(
+        LABEL_OVERLAY_PATH,
+        overlay.iter()
+            .map(|(hash, label)| format!(
+                "0x{:010x}\t{}\t{}\t{}\n",
+                hash,
+                label.label,
+                label.submitter,
+                label.timestamp
+            ))
+            .collect::<String>()
+    )
+}
+
+/// Verify and store a label. Returns whether the label was new.
+pub fn add_label(hash: u64, label: &str, submitter: &str) -> Result<bool, String> {
+    if label.is_empty() || label.contains(|c: char| c == ',' || c == '\t' || c.is_control()) {
+        return Err(String::from("Labels can't be empty or contain commas, tabs or newlines"));
+    }
+
+    let actual = hash40_value(label);
+    if actual != hash {
+        return Err(format!("'{}' hashes to 0x{:010x}, not 0x{:010x}", label, actual, hash));
+    }
+
+    {
+        let mut overlay = OVERLAY.write().unwrap();
+        if let Some(existing) = overlay.get(&hash) {
+            if existing.label == label {
+                return Ok(false);
+            }
+            return Err(format!("0x{:010x} is already labeled '{}' by {}", hash, existing.label, existing.submitter));
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        overlay.insert(hash, CommunityLabel {
+            label: label.to_owned(),
+            submitter: submitter.to_owned(),
+            timestamp,
+        });
+        save_overlay(&overlay).map_err(|e| format!("Failed to save label: {}", e))?;
+    }
+    apply_overlay();
+    set_hash40_labels();
+
+    Ok(true)
+}
+
+pub fn label(s: &str, message: &MessageHelper) {
+    let args = s[5..].trim();
+    let (subcommand, rest) = match args.find(char::is_whitespace) {
+        Some(i) => (&args[..i], args[i..].trim()),
+        None => (args, ""),
+    };
+
+    match subcommand {
+        "add" => {
+            let (hash, label) = match rest.find(char::is_whitespace) {
+                Some(i) => (&rest[..i], rest[i..].trim().trim_matches('"')),
+                None => {
+                    message.say_error(LABEL_USAGE);
+                    return;
+                }
+            };
+            let hash = match u64::from_str_radix(hash.trim_start_matches("0x"), 16) {
+                Ok(hash) => hash,
+                Err(_) => {
+                    message.say_error("Failed to parse. Invalid hex literal.");
+                    return;
+                }
+            };
+
+            match add_label(hash, label, &message.submitter()) {
+                Ok(true) => {
+                    message.say(format!("Added label 0x{:010x} = '{}'", hash, label));
+                }
+                Ok(false) => {
+                    message.say(format!("0x{:010x} is already labeled '{}'", hash, label));
+                }
+                Err(e) => message.say_error(e),
+            }
+        }
+        "export" => {
+            let patch: String = OVERLAY.read()
+                .unwrap()
+                .iter()
+                .map(|(hash, label)| to_csv_line(*hash, &label.label) + "\n")
+                .collect();
+            if patch.is_empty() {
+                message.say("No community labels have been added yet");
+                return;
+            }
+
+            let _ = message.send_file((patch.as_bytes(), "ParamLabels_patch.csv"), "Community labels:")
+                .map_err(|e| {
+                    message.say_error(format!("Error uploading labels: {}", e));
+                });
+        }
+        "stats" => {
+            message.say(
+                MessageBuilder::new()
+                    .push("Label sources, highest precedence first:")
+                    .push_codeblock_safe(stats(), None)
+                    .build()
+            );
+        }
+        _ => message.say_error(LABEL_USAGE),
+    }
+}
+
+const CRACK_USAGE: &str =
+"Use format 'crack [template] [hash]... [time=seconds] [add]'\n\
+Each * in the template is filled with words from an attached wordlist (one per line),\n\
+or with words from known labels when no wordlist is attached. Example: crack *_speed_max 0x0f2ce3c1d5";
+
+/// Longest a crack is allowed to run, in seconds
+const MAX_CRACK_SECONDS: u64 = 30;
+const DEFAULT_CRACK_SECONDS: u64 = 10;
+const MAX_PLACEHOLDERS: usize = 3;
+/// Most matches to send inline before switching to a file upload
+const MAX_CRACK_ROWS: usize = 20;
+
+struct Cracker<'a> {
+    parts: Vec<&'a str>,
+    words: &'a [&'a str],
+    words_by_len: BTreeMap<usize, Vec<&'a str>>,
+    /// full hash40s, which also pin down the length of the string
+    targets: BTreeSet<u64>,
+    /// crc-only (32-bit) hashes, which can't be pruned by length
+    crc_targets: BTreeSet<u64>,
+    deadline: Instant,
+    tries: u64,
+    /// calls to `fill`, which keep going up when pruning leaves no candidates to try
+    steps: u64,
+    timed_out: bool,
+    matches: Vec<(u64, String)>,
+}
+
+impl<'a> Cracker<'a> {
+    fn check(&mut self, candidate: &str) {
+        self.tries += 1;
+        let hash = hash40_value(candidate);
+        if self.targets.contains(&hash) || self.crc_targets.contains(&(hash & 0xFFFF_FFFF)) {
+            self.matches.push((hash, candidate.to_owned()));
+        }
+    }
+
+    fn fill(&mut self, index: usize, candidate: &mut String) {
+        if self.timed_out {
+            return;
+        }
+        self.steps += 1;
+        if self.steps & 0xFFF == 0 && Instant::now() > self.deadline {
+            self.timed_out = true;
+            return;
+        }
+
+        let prefix_len = candidate.len();
+        if index == self.parts.len() - 1 {
+            candidate.push_str(self.parts[index]);
+            self.check(candidate);
+            candidate.truncate(prefix_len);
+            return;
+        }
+
+        let is_last_placeholder = index == self.parts.len() - 2;
+        let suffix_len = self.parts[index + 1].len();
+        let pruned: Vec<&'a str>;
+        let words: &[&'a str] = if is_last_placeholder && self.crc_targets.is_empty() {
+            // the remaining length is fixed by each target, so only those words can match
+            let lengths: BTreeSet<usize> = self.targets
+                .iter()
+                .map(|hash| (hash >> 32) as usize)
+                .filter_map(|len| len.checked_sub(prefix_len + suffix_len + self.parts[index].len()))
+                .collect();
+            pruned = lengths.into_iter()
+                .filter_map(|len| self.words_by_len.get(&len))
+                .flat_map(|words| words.iter().copied())
+                .collect();
+            &pruned
+        } else {
+            self.words
+        };
+
+        candidate.push_str(self.parts[index]);
+        for &word in words {
+            let before_word = candidate.len();
+            candidate.push_str(word);
+            self.fill(index + 1, candidate);
+            candidate.truncate(before_word);
+            if self.timed_out {
+                break;
+            }
+        }
+        candidate.truncate(prefix_len);
+    }
+}
+
+pub fn crack(s: &str, message: &MessageHelper) {
+    let mut args = s[5..].split_whitespace();
+    let template = match args.next() {
+        Some(template) if template.contains('*') => template,
+        _ => {
+            message.say_error(CRACK_USAGE);
+            return;
+        }
+    };
+
+    let mut targets = BTreeSet::new();
+    let mut crc_targets = BTreeSet::new();
+    let mut seconds = DEFAULT_CRACK_SECONDS;
+    let mut add = false;
+    for arg in args {
+        if arg == "add" {
+            add = true;
+        } else if let Some(time) = arg.strip_prefix("time=") {
+            match time.parse::<u64>() {
+                Ok(time) if time > 0 => seconds = std::cmp::min(time, MAX_CRACK_SECONDS),
+                _ => {
+                    message.say_error(CRACK_USAGE);
+                    return;
+                }
+            }
+        } else {
+            for hash in arg.split(',').filter(|hash| !hash.is_empty()) {
+                match u64::from_str_radix(hash.trim_start_matches("0x"), 16) {
+                    Ok(hash) if hash >> 32 == 0 => {
+                        crc_targets.insert(hash);
+                    }
+                    Ok(hash) => {
+                        targets.insert(hash);
+                    }
+                    Err(_) => {
+                        message.say_error(format!("Invalid hash '{}'\n{}", hash, CRACK_USAGE));
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    if targets.is_empty() && crc_targets.is_empty() {
+        message.say_error(CRACK_USAGE);
+        return;
+    }
+
+    let parts: Vec<&str> = template.split('*').collect();
+    if parts.len() - 1 > MAX_PLACEHOLDERS {
+        message.say_error(format!("Templates can have at most {} placeholders", MAX_PLACEHOLDERS));
+        return;
+    }
+
+    let mut wordlist = String::new();
+    for attachment in &message.attachments {
+        match attachment.download() {
+            Ok(content) => wordlist += &String::from_utf8_lossy(&content),
+            Err(e) => {
+                message.say_error(format!("Error downloading '{}': {:?}", attachment.filename, e));
+                return;
+            }
+        }
+        wordlist.push('\n');
+    }
+    let known_words = if wordlist.is_empty() { label_words() } else { Vec::new() };
+    let mut words: Vec<&str> = if wordlist.is_empty() {
+        known_words.iter().map(String::as_str).collect()
+    } else {
+        wordlist.lines().map(str::trim).filter(|word| !word.is_empty()).collect()
+    };
+    words.sort_unstable();
+    words.dedup();
+
+    let mut words_by_len: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+    for word in &words {
+        words_by_len.entry(word.len()).or_default().push(word);
+    }
+
+    message.broadcast_typing();
+
+    let mut cracker = Cracker {
+        parts,
+        words: &words,
+        words_by_len,
+        targets,
+        crc_targets,
+        deadline: Instant::now() + Duration::from_secs(seconds),
+        tries: 0,
+        steps: 0,
+        timed_out: false,
+        matches: Vec::new(),
+    };
+    cracker.fill(0, &mut String::new());
+
+    let mut matches = cracker.matches;
+    matches.sort();
+    matches.dedup();
+
+    let status = format!(
+        "Tried {} candidates from {} words{}. {} matches:",
+        cracker.tries,
+        words.len(),
+        if cracker.timed_out { format!(" before the {}s time limit", seconds) } else { String::new() },
+        matches.len()
+    );
+
+    let rows: Vec<String> = matches
+        .iter()
+        .map(|(hash, label)| {
+            let added = if add {
+                match add_label(*hash, label, &message.submitter()) {
+                    Ok(true) => " (added)".to_owned(),
+                    Ok(false) => " (already added)".to_owned(),
+                    Err(e) => format!(" (not added: {})", e),
+                }
+            } else {
+                String::new()
+            };
+            format!("0x{:010x}  {}{}", hash, label, added)
+        })
+        .collect();
+
+    let table = rows.join("\n");
+    if rows.is_empty() {
+        message.say(status);
+    } else if rows.len() <= MAX_CRACK_ROWS && table.len() < 1800 {
+        message.say(
+            MessageBuilder::new()
+                .push(status)
+                .push_codeblock_safe(table, None)
+                .build()
+        );
+    } else {
+        let table = table + "\n";
+        let _ = message.send_file((table.as_bytes(), "matches.txt"), status)
+            .map_err(|e| {
+                message.say_error(format!("Error uploading matches: {}", e));
+            });
+    }
+}
@@ -4,6 +4,8 @@ extern crate serenity;
 
 mod converter;
 mod arc_commands;
+mod labels;
+mod songs;
 mod message_helper;
 use message_helper::MessageHelper;
 
@@ -36,20 +38,42 @@ use converter::SUPPORTED_TYPES;
 
 static HELP_TEXT: &str = 
 "%convert [args] - convert file even if channel isn't set
-%help - display this message\n\
+%help [arc|songs|hashes] - display this message, or the commands in a section\n\
 %set_channel - watch this channel for files\n\
 %unset_channel - don't watch this channel to watch for files\n\
 %update - update param labels and install paramxml if not installed\n\
+%doctor - check which labels and tools are installed\n\
 %thanks - credits\n\
 %supported_types - print all supported types
 \n\
-Arc commands\n\
-%ls [folder] - list files/folders in arc
-%get [file] - request a file from the arc
-%find_song [song name query] - list songs for a given name
-%get_song [song name query] - download the first song from %find_song
-\n\
-Include 'start,end' or 'start-end' for looping in wav -> nus3audio conversions";
+Include 'start,end' or 'start-end' for looping in wav/mp3/ogg/flac/aiff -> nus3audio conversions, 'autoloop' to find a loop, 'mono' to downmix stereo, 'normalize[=LUFS]' to even out loudness, and 'bitrate=<n>' with 'cbr' or 'vbr' to set the encoding\n\
+Multi-track nus3audio files convert to a zip of every track, include 'track=<name or id>' for just one\n\
+Zip tracks named <id>_<name>.wav (or listed in a tracks.csv of name,id,file) to build a multi-track nus3audio, add a nus3audio to the zip to replace just those tracks, or its nus3bank to take ids from the tones\n\
+nus3bank files convert to a yml of their sections and tones, which converts back once edited";
+
+static ARC_HELP_TEXT: &str =
+"%ls [@version] [folder] [page] [--ext nutexb] [--sort name|size] [-r] - list files/folders in arc with their sizes, compressed too if the data.arc is next to the mount
+%get [@version] [file/folder] [convert [args]] - request a file (or a folder as zip) from the arc, optionally converting it
+%find_file [@version] [--glob|--regex|--fuzzy] [pattern] [page] - search every known arc path
+%arc_versions - list arc versions available besides the default one
+%arc_diff [version] [version] <folder> - list files added, removed or modified between versions in a folder other than the root (arc for the mounted /arc)";
+
+static SONG_HELP_TEXT: &str =
+"%find_song [query] [series:zelda] [game:..] [composer:..] [stage:..] - search songs, with optional filters (composer, arranger and stage are only known for some songs)
+%get_song [query] [wav|ogg|flac] [loop] - download the first song from %find_song, loop plays it twice with a fade
+%song_alias [alias] = [query] - add another name to search a song by
+%random_song [query] - pick a random song, optionally from the songs matching the query
+%songs [series or game] [page] - list the songs from a series or game
+%playlist [wav|ogg|flac] [loop] [query]; [query]; ... - download songs as a zip with a csv of names, filter-only queries add every match
+%song_info [query] - show length, loop points and encoding of a song (or attach a nus3audio)";
+
+static HASH_HELP_TEXT: &str =
+"%hash [hashes or strings...] - look up labels for hash40s, or hash strings (or attach a text file)
+%scan_hashes [align=4|8] [be] [known] - find hash40s in attached files
+%label add [hash] [string] - add a verified label for a hash40
+%label export - download community labels in ParamLabels.csv format
+%label stats - show loaded label sources and lookup statistics
+%crack [template] [hashes...] [time=seconds] [add] - fill each * in template from a wordlist to find hashes";
 
 static THANKS_TEXT: &str = 
 "jam1garner - bot programming, libnus3audio, mscdec/msclang, etc.\n\
@@ -134,6 +158,7 @@ impl EventHandler for Handler {
                     update(&message);
                     return;
                 }
+                "doctor" => doctor(&message),
                 "set_channel" => set_or_unset_channel(self, &message, Set),
                 "unset_channel" => set_or_unset_channel(self, &message, Unset),
                 "help" => {
@@ -146,6 +171,24 @@ impl EventHandler for Handler {
                             .build()
                     );
                 }
+                s if s.starts_with("help ") => {
+                    let (section, text) = match s[5..].trim() {
+                        "arc" => ("Arc", ARC_HELP_TEXT),
+                        "songs" => ("Song", SONG_HELP_TEXT),
+                        "hashes" => ("Hash", HASH_HELP_TEXT),
+                        _ => {
+                            message.say("Unknown help section, use one of: arc, songs, hashes");
+                            return;
+                        }
+                    };
+                    let _ =
+                    message.say(
+                        MessageBuilder::new()
+                            .push(format!("{} commands:", section))
+                            .push_codeblock_safe(text, None)
+                            .build()
+                    );
+                }
                 "thanks" => {
                     let _ =
                     message.say(
@@ -166,9 +209,32 @@ impl EventHandler for Handler {
                 }
                 s @ "ls" | s if s.starts_with("ls ") => arc_commands::ls(s, &message),
                 s if s.starts_with("get ") => arc_commands::get(s, &message),
-                s if s.starts_with("find_song ") => arc_commands::find_song(s, &message),
-                s if s.starts_with("get_song ") => arc_commands::get_song(s, &message),
-                s if s.starts_with("hash ") => arc_commands::hash(s, &message),
+                s if s.starts_with("find_file ") => arc_commands::find_file(s, &message),
+                "arc_versions" => arc_commands::arc_versions(&message),
+                s if s.starts_with("arc_diff ") => arc_commands::arc_diff(s, &message),
+                s if s.starts_with("find_song ") => songs::find_song(s, &message),
+                s if s.starts_with("get_song ") => songs::get_song(s, &message),
+                s if s.starts_with("song_alias ") => songs::song_alias(s, &message),
+                s @ "random_song" | s if s.starts_with("random_song ") => songs::random_song(s, &message),
+                s @ "songs" | s if s.starts_with("songs ") => songs::songs(s, &message),
+                s if s.starts_with("playlist ") => songs::playlist(s, &message),
+                s @ "song_info" | s if s.starts_with("song_info ") => {
+                    songs::song_info(s, &message);
+                    return;
+                }
+                s @ "hash" | s if s.starts_with("hash ") => {
+                    arc_commands::hash(s, &message);
+                    return;
+                }
+                s if s.starts_with("label ") => labels::label(s, &message),
+                s if s.starts_with("crack ") => {
+                    labels::crack(s, &message);
+                    return;
+                }
+                s if s.starts_with("scan_hashes") => {
+                    arc_commands::scan_hashes(s, &message);
+                    return;
+                }
                 _ => {
                     message.say("Invalid command");
                     return;
@@ -191,9 +257,9 @@ impl EventHandler for Handler {
                     return;
                 },
             };
-            let path: PathBuf = ["/tmp/converter/", &attachment.filename].iter().collect(); 
+            let path: PathBuf = [TEMP_DIR, &attachment.filename].iter().collect(); 
 
-            match std::fs::create_dir_all(format!("/tmp/converter")) {
+            match std::fs::create_dir_all(TEMP_DIR) {
                 Ok(()) => {}
                 Err(why) => {
                     println!("Error creating dir: {:?}", why);
@@ -217,44 +283,60 @@ impl EventHandler for Handler {
             }
             
             if match converter::extension(path.as_path()) {
-                "mscsb" | "c" | "wav" | "zip" | "yml" => true,
+                "mscsb" | "c" | "wav" | "mp3" | "ogg" | "flac" | "aiff" | "aif" | "zip" | "yml" => true,
                 s => converter::byml::EXTENSIONS.contains(&s.trim_start_matches("s")),
             } {
                 message.broadcast_typing();
             }
-            match converter::convert(path, message_content) {
-                Ok(path) => {
-                    let _ =
-                    message.send_file(path.to_str().unwrap(), "Converted file")
-                        .map_err(|e|{
-                            message.say(
-                                MessageBuilder::new()
-                                    .push("Error sending file: ")
-                                    .push_codeblock_safe(e.to_string(), None)
-                                    .build()
-                            );
-                        });
-                    std::fs::remove_file(path).unwrap();
+            send_converted(path, message_content, &message);
+        }
+    }
+}
 
-                }
-                Err(why) => {
-                    println!("Error converting file: {:?}", why);
+const TEMP_DIR: &str = "/tmp/converter";
+
+/// Convert the file at `path` and upload the result. The input file is consumed.
+fn send_converted(path: PathBuf, message_content: &str, message: &MessageHelper) {
+    match converter::convert(path, message_content) {
+        Ok((path, notes)) => {
+            let content = std::iter::once(String::from("Converted file"))
+                .chain(notes)
+                .collect::<Vec<_>>()
+                .join("\n");
+            let _ =
+            message.send_file(path.to_str().unwrap(), content)
+                .map_err(|e|{
                     message.say(
                         MessageBuilder::new()
-                            .push("Error converting file:")
-                            .push_codeblock_safe(why.message, None)
+                            .push("Error sending file: ")
+                            .push_codeblock_safe(e.to_string(), None)
                             .build()
                     );
-                }
-            }
+                });
+            std::fs::remove_file(path).unwrap();
+
+        }
+        Err(why) => {
+            println!("Error converting file: {:?}", why);
+            message.say(
+                MessageBuilder::new()
+                    .push("Error converting file:")
+                    .push_codeblock_safe(why.message, None)
+                    .build()
+            );
         }
     }
 }
 
-const MOTION_LABEL_PATH: &str = "motion_list_labels.txt";
-const SQB_LABEL_PATH: &str = "sqb_labels.txt";
 const CHANNELS_PATH: &str = "channels.txt";
 
+/// Reload every label file, along with the arc paths %find_file searches
+fn reload_labels() -> Result<(), String> {
+    let result = labels::reload();
+    arc_commands::reload_arc_paths();
+    result
+}
+
 fn load_channels() -> Vec<ChannelId> {
     fs::read_to_string(CHANNELS_PATH).ok()
         .map(|channels_file|{
@@ -268,8 +350,12 @@ fn load_channels() -> Vec<ChannelId> {
 }
 
 fn main() {
-    arc_commands::setup_songs();
-    update_labels(&[MOTION_LABEL_PATH, SQB_LABEL_PATH]);
+    if let Err(e) = songs::setup_songs() {
+        println!("Failed to load song info: {}", e);
+    }
+    if let Err(missing) = reload_labels() {
+        println!("Some label files could not be loaded, run %update to fetch them:\n{}", missing);
+    }
     let channels = load_channels();
 
     // Login with a bot token from the environment
@@ -281,13 +367,62 @@ fn main() {
     }
 }
 
-fn update_labels(label_paths: &[&str]) {
-    hash40::set_labels(
-        label_paths
-            .iter()
-            .map(|label| hash40::read_labels(label).unwrap())
-            .flatten()
-    )
+/// Files the converters and arc commands need, which `%update` installs
+/// (except for the arc itself)
+const DEPENDENCIES: &[(&str, &str)] = &[
+    ("ParamXML", "paramxml/netcoreapp2.1/ParamXML.dll"),
+    ("VGAudio", "vgaudio/netcoreapp2.0/VGAudioCli.dll"),
+    ("MATLab", "matlab/MatLab.dll"),
+    ("mscdec", "mscdec/mscinfo.xml"),
+    ("msclang", "msclang/mscinfo.xml"),
+    ("ffmpeg", converter::audio_render::FFMPEG_PATH),
+    ("arc", "/arc"),
+];
+
+fn doctor(message: &MessageHelper) {
+    let mut report: Vec<String> = DEPENDENCIES
+        .iter()
+        .map(|(name, path)| {
+            let status = if std::path::Path::new(path).exists() { "ok" } else { "MISSING" };
+            format!("{:<8} {:<10} {}", status, name, path)
+        })
+        .collect();
+
+    let missing_labels = labels::missing_sources();
+    if missing_labels.is_empty() {
+        report.push(format!("{:<8} {:<10} all label files loaded", "ok", "labels"));
+    } else {
+        report.extend(
+            missing_labels
+                .iter()
+                .map(|missing| format!("{:<8} {:<10} {}", "MISSING", "labels", missing))
+        );
+    }
+
+    if let Some(data_arc) = arc_commands::data_arc_path(None) {
+        let status = if data_arc.exists() { "ok" } else { "unused" };
+        report.push(format!(
+            "{:<8} {:<10} {} (optional, compressed sizes in %ls)",
+            status,
+            "data.arc",
+            data_arc.display()
+        ));
+    }
+
+    let arc_overlay = if std::path::Path::new(labels::ARC_LABELS_PATH).exists() { "ok" } else { "unused" };
+    report.push(format!(
+        "{:<8} {:<10} {} (optional, newer labels over the built in ones)",
+        arc_overlay,
+        "arc labels",
+        labels::ARC_LABELS_PATH
+    ));
+
+    message.say(
+        MessageBuilder::new()
+            .push("Run %update to install missing tools and label files. The arc mount has to be set up by hand, optional files can be left unused:")
+            .push_codeblock_safe(report.join("\n"), None)
+            .build()
+    );
 }
 
 fn update(message: &MessageHelper) {
@@ -316,5 +451,12 @@ fn update(message: &MessageHelper) {
                 .build()
         );
     }
-    update_labels(&[MOTION_LABEL_PATH, SQB_LABEL_PATH]);
+    if let Err(e) = reload_labels() {
+        message.say(
+            MessageBuilder::new()
+                .push("Failed to reload labels:")
+                .push_codeblock_safe(e, None)
+                .build()
+        );
+    }
 }
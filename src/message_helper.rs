@@ -1,6 +1,7 @@
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 use serenity::http::AttachmentType;
+use serenity::utils::MessageBuilder;
 
 pub struct MessageHelper {
     message: Message,
@@ -18,10 +19,24 @@ impl MessageHelper {
         self.message.channel_id.say(&self.context.http, message.as_ref()).unwrap()
     }
 
+    pub fn say_error<S: AsRef<str>>(&self, text: S) {
+        self.say(
+            MessageBuilder::new()
+                .push("Error:")
+                .push_codeblock_safe(text.as_ref(), None)
+                .build()
+        );
+    }
+
     pub fn reply<S: AsRef<str>>(&self, message: S) -> Message {
         self.message.reply(self.context.http.clone(), message.as_ref()).unwrap()
     }
 
+    /// Who sent the message, as recorded next to labels and aliases they add
+    pub fn submitter(&self) -> String {
+        format!("{} ({})", self.message.author.tag(), self.message.author.id)
+    }
+
     pub fn broadcast_typing(&self) {
         self.message.channel_id.broadcast_typing(&self.context.http).unwrap()
     }
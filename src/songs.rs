@@ -0,0 +1,1111 @@
+use serenity::utils::MessageBuilder;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use lazy_static::lazy_static;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use super::MessageHelper;
+use super::arc_commands::{self, ZipEntry, ZipSource};
+use super::converter::{self, audio_header::{self, AudioHeader}, audio_render};
+
+const SONG_NAME_CSV: &str = include_str!("song_name_to_file.tsv");
+
+/// Hand-written metadata (composer, stages, alternate titles...) built into
+/// the bot, a yaml map from song id (`a01_smb_chijyou`) to `SongInfo`
+const SONG_INFO: &str = include_str!("song_info.yml");
+/// Optional extra metadata in the same format, loaded over the built in entries
+const SONG_INFO_PATH: &str = "song_info.yml";
+
+/// Aliases added with %song_alias, one `alias\tsong id\tsubmitter` per line
+const SONG_ALIASES_PATH: &str = "song_aliases.tsv";
+
+const BGM_DIR: &str = "/arc/stream:/sound/bgm";
+
+/// Series for each song id prefix, following the sound test's grouping
+const SERIES_PREFIXES: &[(&str, &str)] = &[
+    ("a", "Super Mario"),
+    ("aa", "Super Mario"),
+    ("b", "Donkey Kong"),
+    ("c", "The Legend of Zelda"),
+    ("d", "Metroid"),
+    ("e", "Yoshi"),
+    ("f", "Kirby"),
+    ("g", "Star Fox"),
+    ("h", "Pokémon"),
+    ("i", "F-Zero"),
+    ("j", "Fire Emblem"),
+    ("k", "EarthBound"),
+    ("l", "Pikmin"),
+    ("m", "WarioWare"),
+    ("n", "Animal Crossing"),
+    ("p", "Kid Icarus"),
+    ("s", "Mega Man"),
+    ("u", "Sonic the Hedgehog"),
+    ("v", "Pac-Man"),
+    ("ad", "Castlevania"),
+    ("bk", "Banjo-Kazooie"),
+    ("by", "Bayonetta"),
+    ("dq", "Dragon Quest"),
+    ("ff", "Final Fantasy"),
+    ("gd", "Fatal Fury"),
+    ("mg", "Metal Gear"),
+    ("mh", "Monster Hunter"),
+    ("ps", "Persona"),
+    ("sf", "Street Fighter"),
+    ("sp", "Splatoon"),
+    ("crs", "Super Smash Bros."),
+    ("t", "Super Smash Bros."),
+    ("x", "Super Smash Bros."),
+    ("y", "Super Smash Bros."),
+    ("z", "Super Smash Bros."),
+    ("q", "Other"),
+    ("r", "Other"),
+    ("rr", "Other"),
+    ("w", "Other"),
+];
+
+/// Prefixes that mix songs from many series (the sound test's "Other", the
+/// Melee tracks and the Smash 64 remixes), where the game code after the
+/// prefix decides the series instead, e.g. `r23_xbd_gaurheigen` is Xenoblade
+/// Chronicles and `t13_mr_wakuseizebes` is Metroid
+const MIXED_PREFIXES: &[&str] = &["q", "r", "rr", "t", "w"];
+
+/// Series for each game code used under `MIXED_PREFIXES`. Codes that aren't
+/// listed fall back to the prefix's series in `SERIES_PREFIXES`.
+const SERIES_GAME_CODES: &[(&str, &str)] = &[
+    ("smb", "Super Mario"),
+    ("smb3", "Super Mario"),
+    ("sm64", "Super Mario"),
+    ("wld", "Super Mario"),
+    ("drm", "Super Mario"),
+    ("mtg", "Super Mario"),
+    ("spr", "Donkey Kong"),
+    ("dk64", "Donkey Kong"),
+    ("lnk", "The Legend of Zelda"),
+    ("zld", "The Legend of Zelda"),
+    ("mr", "Metroid"),
+    ("str", "Yoshi"),
+    ("kby", "Kirby"),
+    ("sdx", "Kirby"),
+    ("sfx", "Star Fox"),
+    ("sfx64", "Star Fox"),
+    ("pm", "Pokémon"),
+    ("kg", "Pokémon"),
+    ("fzr", "F-Zero"),
+    ("mtr", "EarthBound"),
+    ("mtr2", "EarthBound"),
+    ("fem", "Fire Emblem"),
+    ("sb", "Super Smash Bros."),
+    ("sbdx", "Super Smash Bros."),
+    ("gw", "Game & Watch"),
+    ("ice", "Ice Climber"),
+    ("bft", "Balloon Fight"),
+    ("rob", "R.O.B."),
+    ("wcr", "Wrecking Crew"),
+    ("dkh", "Duck Hunt"),
+    ("mrd", "Mach Rider"),
+    ("nmj", "The Mysterious Murasame Castle"),
+    ("trs", "Tetris"),
+    ("po", "Punch-Out!!"),
+    ("mpo", "Punch-Out!!"),
+    ("wpo", "Punch-Out!!"),
+    ("pw", "Pilotwings"),
+    ("pwr", "Pilotwings"),
+    ("xbd", "Xenoblade Chronicles"),
+    ("xbd2", "Xenoblade Chronicles"),
+    ("wft", "Wii Fit"),
+    ("wftp", "Wii Fit"),
+    ("wftu", "Wii Fit"),
+    ("wsp", "Wii Sports"),
+    ("wsr", "Wii Sports"),
+    ("wsc", "Wii Sports"),
+    ("wcs", "Wii Sports"),
+    ("ont", "Golden Sun"),
+    ("nou", "Brain Age"),
+    ("motto", "Brain Age"),
+    ("tdcs", "Tomodachi Life"),
+    ("scd", "Find Mii"),
+    ("scd2", "Find Mii"),
+    ("nld", "Nintendo Land"),
+    ("wgf", "Style Savvy"),
+    ("stm", "Code Name: S.T.E.A.M."),
+    ("101", "The Wonderful 101"),
+    ("ut", "Undertale"),
+    ("chd", "Cuphead"),
+];
+
+#[derive(Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct SongInfo {
+    pub title: Option<String>,
+    pub game: Option<String>,
+    pub series: Option<String>,
+    pub composer: Option<String>,
+    pub arranger: Option<String>,
+    pub stages: Vec<String>,
+    /// Other names for the song, such as its Japanese title
+    pub alt_titles: Vec<String>,
+}
+
+impl SongInfo {
+    /// Best guess from the display name and id, `song_info.yml` fills in the rest
+    fn derive(name: &str, id: &str) -> Self {
+        let (title, game) = match name.rfind(" - ") {
+            Some(i) => (&name[..i], Some(name[i + 3..].to_string())),
+            None => (name, None),
+        };
+        let prefix = id.split(|c: char| !c.is_ascii_alphabetic()).next().unwrap_or("");
+        let game_code = id.split('_').nth(1).unwrap_or("");
+
+        let by_game_code = SERIES_GAME_CODES
+            .iter()
+            .filter(|_| MIXED_PREFIXES.contains(&prefix))
+            .find(|(code, _)| *code == game_code);
+        let by_prefix = SERIES_PREFIXES
+            .iter()
+            .find(|(series_prefix, _)| *series_prefix == prefix);
+
+        SongInfo {
+            title: Some(title.to_string()),
+            game,
+            series: by_game_code
+                .or(by_prefix)
+                .map(|(_, series)| series.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn merge(&mut self, other: SongInfo) {
+        self.title = other.title.or_else(|| self.title.take());
+        self.game = other.game.or_else(|| self.game.take());
+        self.series = other.series.or_else(|| self.series.take());
+        self.composer = other.composer.or_else(|| self.composer.take());
+        self.arranger = other.arranger.or_else(|| self.arranger.take());
+        self.stages.extend(other.stages);
+        self.alt_titles.extend(other.alt_titles);
+    }
+}
+
+pub struct Song {
+    pub name: &'static str,
+    pub ids: Vec<&'static str>,
+    pub info: SongInfo,
+    pub aliases: Vec<String>,
+}
+
+impl Song {
+    fn from_line(line: &'static str) -> Option<Song> {
+        let mut columns = line.trim_end_matches('\r').split('\t');
+        let name = columns.next()?;
+        let ids: Vec<_> = columns.filter(|id| !id.is_empty()).collect();
+        if ids.is_empty() {
+            return None;
+        }
+
+        Some(Song {
+            name,
+            info: SongInfo::derive(name, ids[0]),
+            ids,
+            aliases: Vec::new(),
+        })
+    }
+
+    pub fn file_names(&self) -> Vec<String> {
+        self.ids
+            .iter()
+            .map(|id| format!("bgm_{}.nus3audio", id))
+            .collect()
+    }
+
+    pub fn arc_paths(&self) -> Vec<String> {
+        self.file_names()
+            .iter()
+            .map(|file_name| format!("{}/{}", BGM_DIR, file_name))
+            .collect()
+    }
+
+    /// Every name the song can be searched by
+    fn titles(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name)
+            .chain(self.info.title.as_deref())
+            .chain(self.info.alt_titles.iter().map(String::as_str))
+            .chain(self.aliases.iter().map(String::as_str))
+    }
+
+    fn field_values(&self, field: SongField) -> Vec<&str> {
+        match field {
+            SongField::Series => self.info.series.iter().map(String::as_str).collect(),
+            SongField::Game => self.info.game.iter().map(String::as_str).collect(),
+            SongField::Composer => self.info.composer.iter().map(String::as_str).collect(),
+            SongField::Arranger => self.info.arranger.iter().map(String::as_str).collect(),
+            SongField::Stage => self.info.stages.iter().map(String::as_str).collect(),
+            SongField::Title => self.titles().collect(),
+            SongField::Id => self.ids.clone(),
+        }
+    }
+
+    fn matches(&self, field: SongField, value: &str) -> bool {
+        let value = fold(value);
+        self.field_values(field)
+            .into_iter()
+            .any(|field_value| fold(field_value).contains(&value))
+    }
+
+    /// Fuzzy match score of the best matching title, exact matches always win
+    fn score(&self, matcher: &SkimMatcherV2, text: &str) -> Option<i64> {
+        if self.titles().any(|title| fold(title) == fold(text)) {
+            return Some(std::i64::MAX - 1);
+        }
+
+        self.titles()
+            .filter_map(|title| matcher.fuzzy_match(title, text))
+            .max()
+    }
+
+    fn details(&self) -> String {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| String::from("unknown"));
+        let list = |values: &[String]| if values.is_empty() { String::from("none") } else { values.join(", ") };
+
+        let also_known_as: Vec<String> = self.info.alt_titles
+            .iter()
+            .chain(self.aliases.iter())
+            .cloned()
+            .collect();
+
+        format!(
+            "Title:    {}\nGame:     {}\nSeries:   {}\nComposer: {}\nArranger: {}\nStages:   {}\nAKA:      {}\nFiles:    {}",
+            optional(&self.info.title),
+            optional(&self.info.game),
+            optional(&self.info.series),
+            optional(&self.info.composer),
+            optional(&self.info.arranger),
+            list(&self.info.stages),
+            list(&also_known_as),
+            self.file_names().join(", "),
+        )
+    }
+}
+
+/// Lowercase and strip the accents that show up in song metadata, so
+/// `series:pokemon` finds "Pokémon"
+fn fold(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            'é' | 'É' | 'è' | 'ê' => 'e',
+            'á' | 'à' | 'â' => 'a',
+            'ó' | 'ô' => 'o',
+            'ú' | 'ü' => 'u',
+            'í' => 'i',
+            c => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SongField {
+    Series,
+    Game,
+    Composer,
+    Arranger,
+    Stage,
+    Title,
+    Id,
+}
+
+impl SongField {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match &fold(name)[..] {
+            "series" => SongField::Series,
+            "game" => SongField::Game,
+            "composer" => SongField::Composer,
+            "arranger" => SongField::Arranger,
+            "stage" => SongField::Stage,
+            "title" => SongField::Title,
+            "id" => SongField::Id,
+            _ => return None,
+        })
+    }
+}
+
+/// `field:value` filters plus free text that is fuzzy matched against titles
+struct SongQuery {
+    filters: Vec<(SongField, String)>,
+    text: String,
+}
+
+impl SongQuery {
+    fn parse(query: &str) -> Self {
+        let mut filters = Vec::new();
+        let mut text = Vec::new();
+        for token in split_quoted(query) {
+            let filter = token.find(':').and_then(|i| {
+                SongField::from_name(&token[..i]).map(|field| (field, token[i + 1..].to_string()))
+            });
+            match filter {
+                Some((field, value)) if !value.is_empty() => filters.push((field, value)),
+                _ => text.push(token),
+            }
+        }
+
+        SongQuery {
+            filters,
+            text: text.join(" "),
+        }
+    }
+
+    /// Songs matching every filter, best title matches first
+    fn search<'a>(&self, songs: &'a [Song]) -> Vec<&'a Song> {
+        let matcher = SkimMatcherV2::default();
+        let mut results: Vec<(i64, &Song)> = songs
+            .iter()
+            .filter(|song| self.filters.iter().all(|(field, value)| song.matches(*field, value)))
+            .filter_map(|song| {
+                if self.text.is_empty() {
+                    Some((0, song))
+                } else {
+                    song.score(&matcher, &self.text).map(|score| (score, song))
+                }
+            })
+            .collect();
+
+        results.sort_by_key(|(score, song)| (-score, song.name));
+        results.into_iter().map(|(_, song)| song).collect()
+    }
+}
+
+/// Split on whitespace, keeping "quoted strings" (including `series:"star fox"`) together
+fn split_quoted(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+lazy_static! {
+    static ref SONGS: RwLock<Vec<Song>> = RwLock::new(Vec::new());
+}
+
+/// Fill `info` with the built in song info, then `song_info.yml` over it.
+/// Entries loaded before an error are kept.
+fn load_song_info(info: &mut HashMap<String, SongInfo>) -> Result<(), String> {
+    *info = serde_yaml::from_str(SONG_INFO)
+        .map_err(|e| format!("built in song info: {}", e))?;
+    if let Ok(contents) = fs::read_to_string(SONG_INFO_PATH) {
+        let extra: HashMap<String, SongInfo> = serde_yaml::from_str(&contents)
+            .map_err(|e| format!("{}: {}", SONG_INFO_PATH, e))?;
+        for (id, extra) in extra {
+            info.entry(id).or_default().merge(extra);
+        }
+    }
+
+    Ok(())
+}
+
+fn load_aliases() -> Vec<(String, String)> {
+    fs::read_to_string(SONG_ALIASES_PATH)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('\t');
+            Some((columns.next()?.to_string(), columns.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// Build the song database from the built-in song list and song info, an
+/// optional `song_info.yml` and the saved aliases. A broken `song_info.yml`
+/// is reported but the rest still loads.
+pub fn setup_songs() -> Result<(), String> {
+    let mut songs: Vec<Song> = SONG_NAME_CSV
+        .lines()
+        .filter_map(Song::from_line)
+        .collect();
+
+    let mut info = HashMap::new();
+    let loaded = load_song_info(&mut info);
+    for song in &mut songs {
+        for id in &song.ids {
+            if let Some(info) = info.get(*id) {
+                song.info.merge(info.clone());
+            }
+        }
+    }
+
+    for (alias, id) in load_aliases() {
+        if let Some(song) = songs.iter_mut().find(|song| song.ids.contains(&&id[..])) {
+            song.aliases.push(alias);
+        }
+    }
+
+    *SONGS.write().unwrap() = songs;
+
+    loaded
+}
+
+pub fn find_song(s: &str, message: &MessageHelper) {
+    let name = s[9..].trim();
+    let songs = SONGS.read().unwrap();
+    let results = SongQuery::parse(name).search(&songs);
+
+    const LINES: usize = 15;
+
+    match results.as_slice() {
+        [] => {
+            message.say(format!("Song '{}' not found", name));
+        }
+        [song] => {
+            message.say(
+                MessageBuilder::new()
+                    .push(format!("Found '{}':", song.name))
+                    .push_codeblock_safe(song.details(), None)
+                    .build()
+            );
+        }
+        _ => {
+            let mut lines: Vec<String> = results
+                .iter()
+                .take(LINES)
+                .map(|song| format!("{} - {}", song.name, song.file_names().join(", ")))
+                .collect();
+
+            if results.len() > LINES {
+                lines.push(format!("...and {} more", results.len() - LINES));
+            }
+
+            message.say(
+                MessageBuilder::new()
+                    .push(format!("Found for '{}':", name))
+                    .push_codeblock_safe(lines.join("\n"), None)
+                    .build()
+            );
+        }
+    }
+}
+
+const SONG_FORMATS: &[&str] = &["wav", "ogg", "flac"];
+
+/// How many times a song plays through its loop when requested with `loop`,
+/// before fading out over `RENDER_FADE_SECONDS`
+const RENDER_LOOPS: u32 = 2;
+const RENDER_FADE_SECONDS: f64 = 10.0;
+
+/// Vorbis quality levels to try, best first, until the song fits in an upload
+const OGG_QUALITIES: &[u32] = &[6, 4, 2, 0];
+
+struct SongArgs<'a> {
+    name: &'a str,
+    format: Option<&'a str>,
+    looped: bool,
+}
+
+/// Split `"song name" [format] [loop]` or `song name [format] [loop]` into its parts
+fn parse_song_args(args: &str) -> Result<SongArgs<'_>, String> {
+    let args = args.trim();
+    let quoted = args.strip_prefix('"').and_then(|quoted| quoted.find('"').map(|end| (quoted, end)));
+    let (name, options): (&str, Vec<&str>) = match quoted {
+        Some((quoted, end)) => (&quoted[..end], quoted[end + 1..].split_whitespace().collect()),
+        None => {
+            let mut name = args;
+            let mut options = Vec::new();
+            while let Some(i) = name.rfind(char::is_whitespace) {
+                let option = name[i..].trim();
+                if option != "loop" && !SONG_FORMATS.contains(&option) {
+                    break;
+                }
+                options.insert(0, option);
+                name = name[..i].trim_end();
+            }
+            (name, options)
+        }
+    };
+
+    let mut song_args = SongArgs { name, format: None, looped: false };
+    for option in options {
+        match option {
+            "loop" => song_args.looped = true,
+            format if SONG_FORMATS.contains(&format) => song_args.format = Some(format),
+            format => return Err(format!("Unsupported format '{}'. Supported: {}", format, SONG_FORMATS.join(", "))),
+        }
+    }
+
+    Ok(song_args)
+}
+
+/// Loop points of the first track of a nus3audio file
+fn loop_points(path: &Path) -> Option<(u32, u32)> {
+    let data = fs::read(path).ok()?;
+    let nus3_file = audio_header::read_nus3audio(&data).ok()?;
+    AudioHeader::parse(&nus3_file.files.first()?.data)?.loop_points
+}
+
+fn over_upload_limit(path: &Path) -> bool {
+    fs::metadata(path).map(|meta| meta.len() > converter::UPLOAD_LIMIT).unwrap_or(false)
+}
+
+/// Decode a song from the arc through VGAudio, optionally render its loops, and
+/// encode it as `format`. A wav or flac too large to upload is sent as ogg
+/// instead. Returns the file along with a note on how it was rendered.
+fn export_song(arc_path: &Path, format: &str, looped: bool) -> Result<(PathBuf, String), String> {
+    let temp_path = arc_commands::copy_for_conversion(arc_path)
+        .map_err(|e| format!("Could not read '{}': {}", arc_path.display(), e))?;
+    let loop_points = loop_points(&temp_path);
+    let (wav_path, _) = converter::convert(&temp_path, "").map_err(|e| e.message)?;
+
+    let mut note = match (looped, loop_points) {
+        (false, _) => String::new(),
+        (true, None) => String::from(" (the song doesn't loop)"),
+        (true, Some(loop_points)) => {
+            if let Err(e) = audio_render::render_loops(&wav_path, loop_points, RENDER_LOOPS, RENDER_FADE_SECONDS) {
+                let _ = fs::remove_file(&wav_path);
+                return Err(e.message);
+            }
+            format!(" ({} loops + {}s fade)", RENDER_LOOPS, RENDER_FADE_SECONDS)
+        }
+    };
+
+    match format {
+        "wav" if !over_upload_limit(&wav_path) => return Ok((wav_path, note)),
+        "flac" => match audio_render::encode(&wav_path, format, 0) {
+            Ok(path) if over_upload_limit(&path) => {
+                let _ = fs::remove_file(&path);
+            }
+            encoded => {
+                let _ = fs::remove_file(&wav_path);
+                return encoded.map(|path| (path, note)).map_err(|e| e.message);
+            }
+        },
+        _ => {}
+    }
+
+    let mut encoded = Err(String::from("No quality levels to try"));
+    for &quality in OGG_QUALITIES {
+        encoded = audio_render::encode(&wav_path, "ogg", quality).map_err(|e| e.message);
+        match &encoded {
+            Ok(path) if over_upload_limit(path) => continue,
+            _ => break,
+        }
+    }
+    let _ = fs::remove_file(&wav_path);
+
+    if format != "ogg" {
+        note.push_str(&format!(
+            " (sent as ogg, the {} was over the {} upload limit)",
+            format,
+            converter::human_size(converter::UPLOAD_LIMIT)
+        ));
+    }
+    encoded.map(|path| (path, note))
+}
+
+pub fn get_song(s: &str, message: &MessageHelper) {
+    let args = match parse_song_args(&s[8..]) {
+        Ok(args) => args,
+        Err(e) => {
+            message.say_error(e);
+            return;
+        }
+    };
+
+    let songs = SONGS.read().unwrap();
+    let song = match SongQuery::parse(args.name).search(&songs).first() {
+        Some(song) => *song,
+        None => {
+            message.say(format!("Song '{}' not found", args.name));
+            return;
+        }
+    };
+
+    message.say(format!("Song '{}':", song.name));
+    for file_name in song.arc_paths() {
+        let format = match (args.format, args.looped) {
+            (Some(format), _) => format,
+            (None, true) => "wav",
+            (None, false) => {
+                let _ = message.send_file(
+                    &file_name[..],
+                    ""
+                ).map_err(|e| {
+                    message.say(
+                        MessageBuilder::new()
+                            .push(format!("Error uploading '{}':", file_name))
+                            .push_codeblock_safe(e.to_string(), None)
+                            .build()
+                    );
+                });
+                continue;
+            }
+        };
+
+        message.broadcast_typing();
+        let (path, note) = match export_song(Path::new(&file_name), format, args.looped) {
+            Ok(exported) => exported,
+            Err(e) => {
+                message.say_error(format!("Error converting '{}': {}", file_name, e));
+                continue;
+            }
+        };
+
+        let size = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        if size > converter::UPLOAD_LIMIT {
+            message.say_error(format!(
+                "'{}' is {}, over the upload limit of {} even at the lowest ogg quality.",
+                path.display(),
+                converter::human_size(size),
+                converter::human_size(converter::UPLOAD_LIMIT)
+            ));
+        } else {
+            let _ = message.send_file(
+                path.to_str().unwrap(),
+                format!("Converted file{}", note)
+            ).map_err(|e| {
+                message.say_error(format!("Error uploading '{}': {}", path.display(), e));
+            });
+        }
+        let _ = fs::remove_file(&path);
+    }
+}
+
+pub fn random_song(s: &str, message: &MessageHelper) {
+    let query = s[11..].trim();
+    let songs = SONGS.read().unwrap();
+    let results: Vec<&Song> = if query.is_empty() {
+        songs.iter().collect()
+    } else {
+        SongQuery::parse(query).search(&songs)
+    };
+
+    match results.choose(&mut rand::thread_rng()) {
+        Some(song) => {
+            message.say(
+                MessageBuilder::new()
+                    .push(format!("'{}':", song.name))
+                    .push_codeblock_safe(song.details(), None)
+                    .push(format!("Use %get_song \"{}\" to download it", song.name))
+                    .build()
+            );
+        }
+        None => {
+            message.say(format!("No songs found for '{}'", query));
+        }
+    }
+}
+
+const SONGS_USAGE: &str = "Use format 'songs [series or game] [page]', or filters like 'songs composer:sakurai'";
+const NUM_LINES: usize = 15;
+
+pub fn songs(s: &str, message: &MessageHelper) {
+    let mut args = s[5..].trim();
+    let page = match args.rfind(char::is_whitespace) {
+        Some(i) => match usize::from_str_radix(args[i..].trim(), 10) {
+            Ok(num) if num > 0 => {
+                args = args[..i].trim_end();
+                num
+            }
+            _ => 1,
+        },
+        None => 1,
+    };
+
+    if args.is_empty() {
+        message.say_error(SONGS_USAGE);
+        return;
+    }
+
+    let songs = SONGS.read().unwrap();
+    let query = SongQuery::parse(args);
+    let results: Vec<&Song> = if query.filters.is_empty() {
+        // kept in song id order, which groups each game's songs together
+        songs
+            .iter()
+            .filter(|song| song.matches(SongField::Series, args) || song.matches(SongField::Game, args))
+            .collect()
+    } else {
+        query.search(&songs)
+    };
+
+    if results.is_empty() {
+        message.say(format!("No songs found for '{}'", args));
+        return;
+    }
+
+    let pages = arc_commands::page_count(results.len());
+    if page > pages {
+        message.say_error(format!("Page {} is out of range, there are {} pages", page, pages));
+        return;
+    }
+
+    let lines = results
+        .iter()
+        .skip((page - 1) * NUM_LINES)
+        .take(NUM_LINES)
+        .map(|song| format!("{} - {}", song.name, song.ids.join(", ")))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    message.say(
+        MessageBuilder::new()
+            .push(format!("{} songs for '{}' (page {}/{}):", results.len(), args, page, pages))
+            .push_codeblock_safe(lines, None)
+            .build()
+    );
+}
+
+const PLAYLIST_USAGE: &str = "Use format 'playlist [wav|ogg|flac] [loop] [query]; [query]; ...'. A query with only filters, like series:zelda, adds every match.";
+
+/// Most songs a single playlist can hold
+const MAX_PLAYLIST_SONGS: usize = 30;
+
+/// Quote a CSV field if it needs it
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn playlist_entry(arc_path: &str, id: &str, format: Option<&str>, looped: bool) -> Result<ZipEntry, String> {
+    match format {
+        None => {
+            let size = fs::metadata(arc_path)
+                .map_err(|e| format!("Could not read '{}': {}", arc_path, e))?
+                .len();
+            Ok(ZipEntry {
+                name: format!("bgm_{}.nus3audio", id),
+                size,
+                source: ZipSource::Arc(PathBuf::from(arc_path)),
+            })
+        }
+        Some(format) => {
+            // oversized wav and flac exports come back as ogg
+            let (path, _) = export_song(Path::new(arc_path), format, looped)?;
+            let data = fs::read(&path).map_err(|e| e.to_string());
+            let _ = fs::remove_file(&path);
+            let data = data?;
+            Ok(ZipEntry {
+                name: format!("bgm_{}.{}", id, converter::extension(&path)),
+                size: data.len() as u64,
+                source: ZipSource::Converted(data),
+            })
+        }
+    }
+}
+
+/// Rough size of a converted song per byte of its nus3audio, for the ~64 kbps
+/// opus most songs use: 48 kHz stereo wav is about 24 times larger, flac about
+/// 14 times and ogg at the first quality tried about 3 times
+fn expansion_ratio(format: Option<&str>) -> u64 {
+    match format {
+        None => 1,
+        Some("ogg") => 3,
+        Some("flac") => 14,
+        _ => 24,
+    }
+}
+
+/// Estimated size of a playlist from the size of its songs in the arc, so
+/// a playlist that can't be sent is refused before converting anything
+fn estimate_playlist_size(songs: &[&Song], format: Option<&str>, looped: bool) -> u64 {
+    let arc_size: u64 = songs
+        .iter()
+        .flat_map(|song| song.arc_paths())
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    // the intro, two loops and the fade come to about twice the song
+    let loop_ratio = if looped { 2 } else { 1 };
+
+    arc_size * expansion_ratio(format) * loop_ratio
+}
+
+/// Zip up a list of songs along with a `playlist.csv` mapping song names to files
+pub fn playlist(s: &str, message: &MessageHelper) {
+    let mut args = s[8..].trim();
+    let mut format = None;
+    let mut looped = false;
+    loop {
+        let (option, rest) = match args.find(char::is_whitespace) {
+            Some(i) => (&args[..i], args[i..].trim_start()),
+            None => break,
+        };
+        match option {
+            "loop" => looped = true,
+            option if SONG_FORMATS.contains(&option) => format = Some(option),
+            _ => break,
+        }
+        args = rest;
+    }
+    if looped && format.is_none() {
+        format = Some("wav");
+    }
+
+    let songs = SONGS.read().unwrap();
+    let mut selected: Vec<&Song> = Vec::new();
+    let mut not_found = Vec::new();
+    for query in args.split(';').map(str::trim).filter(|query| !query.is_empty()) {
+        let parsed = SongQuery::parse(query);
+        let results = parsed.search(&songs);
+        let picked = if parsed.text.is_empty() { &results[..] } else { &results[..results.len().min(1)] };
+        if picked.is_empty() {
+            not_found.push(query);
+        }
+        for song in picked {
+            if !selected.iter().any(|selected| std::ptr::eq(*selected, *song)) {
+                selected.push(song);
+            }
+        }
+    }
+
+    if !not_found.is_empty() {
+        message.say(format!("Songs not found: {}", not_found.join(", ")));
+    }
+    if selected.is_empty() {
+        message.say_error(PLAYLIST_USAGE);
+        return;
+    }
+    if selected.len() > MAX_PLAYLIST_SONGS {
+        message.say_error(format!(
+            "The playlist has {} songs, the most that can be exported at once is {}",
+            selected.len(),
+            MAX_PLAYLIST_SONGS
+        ));
+        return;
+    }
+
+    let estimated_size = estimate_playlist_size(&selected, format, looped);
+    let max_size = arc_commands::MAX_ZIP_PARTS as u64 * converter::UPLOAD_LIMIT;
+    if estimated_size > max_size {
+        message.say_error(format!(
+            "The playlist would be about {} as {}, more than the {} zip parts of {} it can be split into. Try fewer songs{}.",
+            converter::human_size(estimated_size),
+            format.unwrap_or("nus3audio"),
+            arc_commands::MAX_ZIP_PARTS,
+            converter::human_size(converter::UPLOAD_LIMIT),
+            if format == Some("wav") || format == Some("flac") { " or ogg" } else { "" }
+        ));
+        return;
+    }
+
+    message.broadcast_typing();
+
+    let mut manifest = String::from("name,id,file\n");
+    let mut entries = Vec::new();
+    let mut failed = Vec::new();
+    for song in &selected {
+        for (id, arc_path) in song.ids.iter().zip(song.arc_paths()) {
+            match playlist_entry(&arc_path, id, format, looped) {
+                Ok(entry) => {
+                    manifest.push_str(&format!("{},bgm_{},{}\n", csv_field(song.name), id, entry.name));
+                    entries.push(entry);
+                }
+                Err(e) => failed.push(format!("{}: {}", song.name, e)),
+            }
+        }
+    }
+
+    if !entries.is_empty() {
+        let manifest = manifest.into_bytes();
+        entries.insert(0, ZipEntry {
+            name: String::from("playlist.csv"),
+            size: manifest.len() as u64,
+            source: ZipSource::Converted(manifest),
+        });
+        arc_commands::send_zip(entries, "playlist", &format!("Playlist of {} songs", selected.len()), message);
+    }
+
+    if !failed.is_empty() {
+        message.say(
+            MessageBuilder::new()
+                .push(format!("{} songs could not be exported:", failed.len()))
+                .push_codeblock_safe(failed.join("\n"), None)
+                .build()
+        );
+    }
+}
+
+/// Length, loop points and encoding of every track in a nus3audio
+fn nus3audio_info(data: &[u8]) -> String {
+    let nus3_file = match audio_header::read_nus3audio(data) {
+        Ok(nus3_file) => nus3_file,
+        Err(e) => return e,
+    };
+    if nus3_file.files.is_empty() {
+        return String::from("No tracks found");
+    }
+
+    nus3_file.files
+        .iter()
+        .map(|track| {
+            let header = match AudioHeader::parse(&track.data) {
+                Some(header) => header,
+                None => return format!("Track {} '{}': unknown encoding", track.id, track.name),
+            };
+            let loop_points = match header.loop_points {
+                Some((start, end)) => format!(
+                    "{} - {} (samples {} - {})",
+                    header.timestamp(start),
+                    header.timestamp(end),
+                    start,
+                    end
+                ),
+                None => String::from("none"),
+            };
+
+            format!(
+                "Track {} '{}': {}, {} ch, {} Hz\n  Length:  {} ({} samples)\n  Loop:    {}\n  Bitrate: {} kbps",
+                track.id,
+                track.name,
+                header.encoding.name(),
+                header.channels,
+                header.sample_rate,
+                header.timestamp(header.sample_count),
+                header.sample_count,
+                loop_points,
+                header.bitrate(track.data.len()) / 1000,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Send a nus3audio's track info, as a text file when a voice or sound pack
+/// has too many tracks to fit in a message
+fn send_track_info(message: &MessageHelper, title: String, file_name: &str, info: String) {
+    if title.len() + info.len() < 1900 {
+        message.say(
+            MessageBuilder::new()
+                .push(title)
+                .push_codeblock_safe(info, None)
+                .build()
+        );
+    } else {
+        let file_name = format!("{}.txt", Path::new(file_name).file_stem().unwrap_or_default().to_string_lossy());
+        let info = info + "\n";
+        let _ = message.send_file((info.as_bytes(), &file_name[..]), title)
+            .map_err(|e| {
+                message.say_error(format!("Error uploading track info: {}", e));
+            });
+    }
+}
+
+/// Show track info for a song by name, or for attached nus3audio files
+pub fn song_info(s: &str, message: &MessageHelper) {
+    let name = s[9..].trim();
+
+    if name.is_empty() {
+        if message.attachments.is_empty() {
+            message.say_error("Use format 'song_info [song name query]' or attach a nus3audio");
+            return;
+        }
+
+        for attachment in &message.attachments {
+            match attachment.download() {
+                Ok(data) => send_track_info(
+                    message,
+                    format!("'{}':", attachment.filename),
+                    &attachment.filename,
+                    nus3audio_info(&data),
+                ),
+                Err(e) => message.say_error(format!("Error downloading '{}': {:?}", attachment.filename, e)),
+            }
+        }
+        return;
+    }
+
+    let songs = SONGS.read().unwrap();
+    let song = match SongQuery::parse(name).search(&songs).first() {
+        Some(song) => *song,
+        None => {
+            message.say(format!("Song '{}' not found", name));
+            return;
+        }
+    };
+
+    for path in song.arc_paths() {
+        match fs::read(&path) {
+            Ok(data) => {
+                let file_name = Path::new(&path).file_name().unwrap().to_string_lossy().into_owned();
+                send_track_info(
+                    message,
+                    format!("'{}' ({}):", song.name, file_name),
+                    &file_name,
+                    nus3audio_info(&data),
+                );
+            }
+            Err(e) => message.say_error(format!("Could not read '{}': {}", path, e)),
+        }
+    }
+}
+
+const SONG_ALIAS_USAGE: &str = "Use format 'song_alias [alias] = [song name query]'";
+
+pub fn song_alias(s: &str, message: &MessageHelper) {
+    let (alias, query) = match s[11..].find('=') {
+        Some(i) => (s[11..11 + i].trim().trim_matches('"'), s[12 + i..].trim()),
+        None => {
+            message.say_error(SONG_ALIAS_USAGE);
+            return;
+        }
+    };
+
+    if alias.is_empty() || query.is_empty() {
+        message.say_error(SONG_ALIAS_USAGE);
+        return;
+    }
+    if alias.contains(|c: char| c == '\t' || c == '"' || c.is_control()) {
+        message.say_error("Aliases can't contain tabs, quotes or newlines");
+        return;
+    }
+
+    let mut songs = SONGS.write().unwrap();
+    if let Some(song) = songs.iter().find(|song| song.titles().any(|title| fold(title) == fold(alias))) {
+        message.say_error(format!("'{}' already refers to '{}'", alias, song.name));
+        return;
+    }
+
+    let index = match SongQuery::parse(query).search(&songs).first() {
+        Some(song) => songs.iter().position(|other| std::ptr::eq(other, *song)).unwrap(),
+        None => {
+            message.say(format!("Song '{}' not found", query));
+            return;
+        }
+    };
+
+    let song = &mut songs[index];
+    let saved = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(SONG_ALIASES_PATH)
+        .and_then(|mut file| {
+            writeln!(file, "{}\t{}\t{}", alias, song.ids[0], message.submitter())
+        });
+
+    match saved {
+        Ok(()) => {
+            song.aliases.push(alias.to_string());
+            message.say(format!("Added alias '{}' for '{}'", alias, song.name));
+        }
+        Err(e) => message.say_error(format!("Failed to save alias: {}", e)),
+    }
+}
@@ -33,3 +33,9 @@ wget https://raw.githubusercontent.com/ultimate-research/param-labels/master/sqb
     mv Labels.txt sqb_labels.txt &&\
     echo "Installed SQB labels"
 
+rm -rf ffmpeg/ &&\
+    wget https://johnvansickle.com/ffmpeg/releases/ffmpeg-release-amd64-static.tar.xz > /dev/null &&\
+    mkdir ffmpeg &&\
+    tar -xJf ffmpeg-release-amd64-static.tar.xz -C ffmpeg --strip-components=1 &&\
+    rm ffmpeg-release-amd64-static.tar.xz &&\
+    echo "Installed ffmpeg"